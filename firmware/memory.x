@@ -1,6 +1,7 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 64K
+    /* Last 64K (0x101F0000) hold calibration and stored operations, see hexa_config */
 
     /* Pick one of the two options for RAM layout     */
 
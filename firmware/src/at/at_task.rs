@@ -4,23 +4,23 @@
 use cortex_m::peripheral::SCB;
 use defmt::{error, info};
 use embassy_executor::Spawner;
-use heapless::String;
-use {defmt_rtt as _, panic_probe as _};
 
-use hexa_tune_proto_embedded::command::HexaCommand;
 use hexa_tune_proto_embedded::HexaError;
+use hexa_tune_proto_embedded::command::HexaCommand;
 
 use crate::AT_CH;
 use crate::USB_CH;
 use crate::at::*;
 use crate::channel::*;
+use crate::dds::{GENERATE_CTRL, GenerateCtrl, progress_status, stream_bulk, stream_open};
 use crate::error::FirmwareError;
 use crate::hexa_config::*;
+use crate::usb::{encode_nak, take_bulk};
 
 #[embassy_executor::task]
 pub async fn at_task(spawner: Spawner) {
     info!("Starting AT task");
-    let mut last_operation_status: String<64> = String::new();
+    let mut last_operation_status = MsgString::new();
     loop {
         match AT_CH.receive().await {
             Msg::AtRxLine(line) => {
@@ -50,10 +50,27 @@ pub async fn at_task(spawner: Spawner) {
             Msg::SetOperationStatus(status) => {
                 last_operation_status = status;
             }
+            Msg::BulkChunk(id) => {
+                // An open stream takes chunks while the DDS is busy playing it
+                if stream_open() {
+                    let line = stream_bulk(id).unwrap_or_else(|e| encode_error_response(id, &e));
+                    USB_CH.send(Msg::UsbTxLine(line)).await;
+                    continue;
+                }
+                let taken = is_dds_available() && spawner.spawn(bulk_task(id)).is_ok();
+                if !taken {
+                    error!("DDS busy, cannot take bulk chunk");
+                    // Drop only this chunk, others may still be on their way
+                    take_bulk(id);
+                    let nak = encode_nak(id, &FirmwareError::Hexa(HexaError::DdsBusy))
+                        .unwrap_or_else(|e| encode_error_response(id, &e));
+                    USB_CH.send(Msg::UsbTxLine(nak)).await;
+                }
+            }
             Msg::GetOperationStatus => {
-                USB_CH
-                    .send(Msg::UsbTxLine(last_operation_status.clone()))
-                    .await;
+                // A running GENERATE reports its position as of now
+                let status = progress_status().unwrap_or_else(|| last_operation_status.clone());
+                USB_CH.send(Msg::UsbTxLine(status)).await;
             }
             _ => {}
         }
@@ -61,7 +78,13 @@ pub async fn at_task(spawner: Spawner) {
 }
 
 fn dispatch_and_spawn(spawner: Spawner, payload: &[u8]) -> Result<(), (u32, FirmwareError)> {
-    let cmd = dispatch_at_payload(payload).map_err(|e| (0u32, e))?;
+    match dispatch_at_payload(payload).map_err(|e| (0u32, e))? {
+        Command::Hexa(cmd) => dispatch_hexa(spawner, cmd),
+        Command::Fw(cmd) => dispatch_fw(spawner, cmd),
+    }
+}
+
+fn dispatch_hexa(spawner: Spawner, cmd: HexaCommand) -> Result<(), (u32, FirmwareError)> {
     let id = command_id(&cmd);
 
     match cmd {
@@ -81,15 +104,82 @@ fn dispatch_and_spawn(spawner: Spawner, payload: &[u8]) -> Result<(), (u32, Firm
             info!("Dispatching FWUPDATE command");
             spawner.spawn(fwupdate_task()).ok();
         }
-        HexaCommand::Freq { id, freq, time_ms } => {
+        HexaCommand::OperationQuery => {
+            info!("Dispatching OPERATION query");
+            spawner.spawn(operation_status_task()).ok();
+        }
+        _ => {
+            return Err((id, FirmwareError::Hexa(HexaError::UnknownCommand)));
+        }
+    }
+    Ok(())
+}
+
+fn dispatch_fw(spawner: Spawner, cmd: FwCommand) -> Result<(), (u32, FirmwareError)> {
+    match cmd {
+        FwCommand::Freq {
+            id,
+            freq,
+            time_ms,
+            phase,
+            glide,
+            colour,
+        } => {
             if !is_dds_available() {
                 error!("DDS busy, cannot set FREQ");
                 return Err((id, FirmwareError::Hexa(HexaError::DdsBusy)));
             }
             info!("Dispatching FREQ command");
-            spawner.spawn(freq_task(id, freq, time_ms)).ok();
+            spawner
+                .spawn(freq_task(id, freq, time_ms, phase, glide, colour))
+                .ok();
+        }
+        FwCommand::Sweep {
+            id,
+            start,
+            time_ms,
+            sweep,
+        } => {
+            if !is_dds_available() {
+                error!("DDS busy, cannot add SWEEP");
+                return Err((id, FirmwareError::Hexa(HexaError::DdsBusy)));
+            }
+            info!("Dispatching SWEEP command");
+            spawner.spawn(sweep_task(id, start, time_ms, sweep)).ok();
+        }
+        FwCommand::Phase { id, phase } => {
+            if !is_dds_available() {
+                error!("DDS busy, cannot set PHASE");
+                return Err((id, FirmwareError::Hexa(HexaError::DdsBusy)));
+            }
+            info!("Dispatching PHASE command");
+            spawner.spawn(phase_task(id, phase)).ok();
+        }
+        FwCommand::Waveform { id, waveform } => {
+            if !is_dds_available() {
+                error!("DDS busy, cannot set WAVEFORM");
+                return Err((id, FirmwareError::Hexa(HexaError::DdsBusy)));
+            }
+            info!("Dispatching WAVEFORM command");
+            spawner.spawn(waveform_task(id, waveform)).ok();
+        }
+        FwCommand::Operation {
+            id,
+            sub: sub @ (OperationSub::Stop | OperationSub::Pause | OperationSub::Resume),
+        } => {
+            // The DDS task is busy generating, so these go straight to the dwell wait
+            if is_dds_available() {
+                error!("No operation is generating");
+                return Err((id, FirmwareError::NotGenerating));
+            }
+            info!("Signalling OPERATION control request");
+            GENERATE_CTRL.signal(match sub {
+                OperationSub::Pause => GenerateCtrl::Pause { id },
+                OperationSub::Resume => GenerateCtrl::Resume { id },
+                _ => GenerateCtrl::Stop { id },
+            });
         }
-        HexaCommand::Operation { id, sub } => {
+        FwCommand::Operation { id, sub } => {
             if !is_dds_available() {
                 error!("DDS busy, cannot set OPERATION");
                 return Err((id, FirmwareError::Hexa(HexaError::DdsBusy)));
@@ -97,12 +187,61 @@ fn dispatch_and_spawn(spawner: Spawner, payload: &[u8]) -> Result<(), (u32, Firm
             info!("Dispatching OPERATION command");
             spawner.spawn(operation_task(id, sub)).ok();
         }
-        HexaCommand::OperationQuery => {
-            info!("Dispatching OPERATION query");
-            spawner.spawn(operation_status_task()).ok();
+        FwCommand::Slot { id, sub } => {
+            if !is_dds_available() {
+                error!("DDS busy, cannot access SLOT");
+                return Err((id, FirmwareError::Hexa(HexaError::DdsBusy)));
+            }
+            info!("Dispatching SLOT command");
+            spawner.spawn(slot_task(id, sub)).ok();
         }
-        _ => {
-            return Err((id, FirmwareError::Hexa(HexaError::UnknownCommand)));
+        // Streamed steps arrive while the DDS is busy generating
+        FwCommand::StreamStep { id, step } => {
+            info!("Dispatching STREAM step");
+            if spawner.spawn(stream_step_task(id, step)).is_err() {
+                error!("STREAM steps arriving faster than they are queued");
+                return Err((id, FirmwareError::StreamFull));
+            }
+        }
+        FwCommand::StreamEnd { id } => {
+            info!("Dispatching STREAM end");
+            spawner.spawn(stream_end_task(id)).ok();
+        }
+        FwCommand::FreqInfo { id, freq } => {
+            if !is_dds_available() {
+                error!("DDS busy, cannot answer FREQINFO");
+                return Err((id, FirmwareError::Hexa(HexaError::DdsBusy)));
+            }
+            info!("Dispatching FREQINFO command");
+            spawner.spawn(freq_info_task(id, freq)).ok();
+        }
+        FwCommand::Calibrate { id, sub } => {
+            if !is_dds_available() {
+                error!("DDS busy, cannot CALIBRATE");
+                return Err((id, FirmwareError::Hexa(HexaError::DdsBusy)));
+            }
+            info!("Dispatching CALIBRATE command");
+            spawner.spawn(calibrate_task(id, sub)).ok();
+        }
+        FwCommand::CalibrateQuery => {
+            if !is_dds_available() {
+                error!("DDS busy, cannot answer CALIBRATE query");
+                return Err((0, FirmwareError::Hexa(HexaError::DdsBusy)));
+            }
+            info!("Dispatching CALIBRATE query");
+            spawner.spawn(calibrate_query_task()).ok();
+        }
+        FwCommand::Autostart { id, enabled } => {
+            if !is_dds_available() {
+                error!("DDS busy, cannot set AUTOSTART");
+                return Err((id, FirmwareError::Hexa(HexaError::DdsBusy)));
+            }
+            info!("Dispatching AUTOSTART command");
+            spawner.spawn(autostart_task(id, enabled)).ok();
+        }
+        FwCommand::AutostartQuery => {
+            info!("Dispatching AUTOSTART query");
+            spawner.spawn(autostart_query_task()).ok();
         }
     }
     Ok(())
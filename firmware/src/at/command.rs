@@ -0,0 +1,1055 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use hexa_tune_proto::at::{AtMessage, AtOp, Params};
+use hexa_tune_proto_embedded::HexaError;
+use hexa_tune_proto_embedded::command::HexaCommand;
+
+use crate::dds::{
+    CAL_PPB_LIMIT, Glide, MilliHertz, PHASE_STEPS, Rgb, StepMode, Sweep, SweepCurve, Waveform,
+};
+use crate::storage::{SLOT_COUNT, SlotName, is_valid_slot_name};
+
+/// A resolved AT command, either from the shared hexaTune command set or
+/// one the firmware resolves itself.
+pub enum Command {
+    Hexa(HexaCommand),
+    Fw(FwCommand),
+}
+
+/// Sub-commands of `AT+OPERATION=id#SUB[#...]`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OperationSub {
+    /// `PREPARE[#repeat]`, play the steps `repeat` times (default 1, 0 loops until stopped)
+    Prepare { repeat: u32 },
+    /// `GENERATE[#CONTINUOUS|#RESET][#SLOT#n][#opId]`, from slot `n` instead of
+    /// the prepared operation when given, refused unless the operation is `opId`
+    Generate {
+        mode: StepMode,
+        slot: Option<u8>,
+        operation_id: Option<u32>,
+    },
+    /// `STREAM[#CONTINUOUS|#RESET]`, play steps sent with AT+STREAM as they arrive
+    Stream { mode: StepMode },
+    /// `ARM#delayMs[#CONTINUOUS|#RESET][#SLOT#n][#opId]`, GENERATE once `delay_ms` has passed
+    Arm {
+        delay_ms: u32,
+        mode: StepMode,
+        slot: Option<u8>,
+        operation_id: Option<u32>,
+    },
+    /// `SAVE`, write the prepared operation to flash
+    Save,
+    /// `LOAD`, replace the prepared operation with the one saved in flash
+    Load,
+    /// `STOP`, abort a running GENERATE
+    Stop,
+    /// `PAUSE`, power down a running GENERATE and hold its position
+    Pause,
+    /// `RESUME`, continue a paused GENERATE
+    Resume,
+    /// `TIMING[#index]`, start errors of the last GENERATE
+    Timing { index: Option<u32> },
+    /// `VALIDATE[#opId]`, check the prepared operation as GENERATE would and
+    /// report its session length without driving the DDS
+    Validate { operation_id: Option<u32> },
+    /// `INFO`, id, step count, repeat count and duration of the prepared operation
+    Info,
+    /// `STEPS[#start[#count]]`, read back `count` steps from index `start`
+    Steps { start: u32, count: u32 },
+    /// `REPLACE#ID|INDEX#n#freq#timeMs[#phase][#GLIDE#glideMs[#LIN|EXP]][#RGB#r#g#b]`,
+    /// overwrite a step, keeping its id
+    Replace { target: StepRef, step: StepParams },
+    /// `INSERT#ID|INDEX#n#freq#timeMs[#phase][#GLIDE#glideMs[#LIN|EXP]][#RGB#r#g#b]`,
+    /// add a step before the target
+    Insert { target: StepRef, step: StepParams },
+    /// `DELETE#ID|INDEX#n`, remove a step
+    Delete { target: StepRef },
+    /// `TRUNCATE#len`, keep only the first `len` steps
+    Truncate { len: u32 },
+}
+
+/// Step addressed by an editing sub-command.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StepRef {
+    /// `ID#n`, the step added by AT command `n`
+    Id(u32),
+    /// `INDEX#n`, the step at 0-based position `n`
+    Index(u32),
+}
+
+/// Fixed-frequency step given to REPLACE or INSERT.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct StepParams {
+    pub freq: MilliHertz,
+    pub time_ms: u32,
+    pub phase: Option<u8>,
+    pub glide: Option<Glide>,
+    pub colour: Option<Rgb>,
+}
+
+/// Sub-commands of `AT+SLOT=id#SUB[#...]`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum SlotSub {
+    /// `LIST`, one line per stored slot
+    List,
+    /// `STORE#n#name`, write the prepared operation to slot `n`
+    Store { slot: u8, name: SlotName },
+    /// `LOAD#n`, replace the prepared operation with slot `n`
+    Load { slot: u8 },
+    /// `DELETE#n`, erase slot `n`
+    Delete { slot: u8 },
+}
+
+/// Sub-commands of `AT+CALIBRATE=id#SUB#...`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CalibrateSub {
+    /// `PPB#<ppb>` or `PPM#<ppm>`, set the reference clock correction directly
+    Offset { ppb: i32 },
+    /// `MEASURED#<requested>#<measured>`, derive the correction from the output
+    /// measured for a FREQ of `requested` under the current correction
+    Measured {
+        requested: MilliHertz,
+        measured: MilliHertz,
+    },
+}
+
+/// Commands (or command forms) not covered by `hexa_tune_proto_embedded`.
+pub enum FwCommand {
+    /// `AT+FREQ=id#freq#timeMs[#phase][#GLIDE#glideMs[#LIN|EXP]][#RGB#r#g#b]`,
+    /// with `freq` in Hz and up to three decimals
+    Freq {
+        id: u32,
+        freq: MilliHertz,
+        time_ms: u32,
+        phase: Option<u8>,
+        glide: Option<Glide>,
+        colour: Option<Rgb>,
+    },
+    /// `AT+SWEEP=id#start#end#timeMs#LIN|LOG[#updateMs]`
+    Sweep {
+        id: u32,
+        start: MilliHertz,
+        time_ms: u32,
+        sweep: Sweep,
+    },
+    /// `AT+PHASE=id#phase`, the default phase offset in 11.25 degree steps
+    Phase { id: u32, phase: u8 },
+    /// `AT+WAVEFORM=id#SINE|TRIANGLE|SQUARE`
+    Waveform { id: u32, waveform: Waveform },
+    /// `AT+OPERATION=id#SUB[#...]`
+    Operation { id: u32, sub: OperationSub },
+    /// `AT+SLOT=id#SUB[#...]`, named operations kept in flash
+    Slot { id: u32, sub: SlotSub },
+    /// `AT+STREAM=id#freq#timeMs[#phase][#GLIDE#glideMs[#LIN|EXP]][#RGB#r#g#b]`,
+    /// queue a step for a streaming GENERATE
+    StreamStep { id: u32, step: StepParams },
+    /// `AT+STREAM=id#END`, finish a streaming GENERATE after the queued steps
+    StreamEnd { id: u32 },
+    /// `AT+FREQINFO=id#freq`, the achieved frequency for `freq` without adding a step
+    FreqInfo { id: u32, freq: MilliHertz },
+    /// `AT+CALIBRATE=id#SUB#...`
+    Calibrate { id: u32, sub: CalibrateSub },
+    /// `AT+CALIBRATE?`
+    CalibrateQuery,
+    /// `AT+AUTOSTART=id#ON|OFF`, generate the saved operation after power-up
+    Autostart { id: u32, enabled: bool },
+    /// `AT+AUTOSTART?`
+    AutostartQuery,
+}
+
+/// Tuning word update interval of a SWEEP without an explicit one.
+const SWEEP_DEFAULT_UPDATE_MS: u32 = 10;
+
+/// Steps returned by `AT+OPERATION=id#STEPS` without an explicit count.
+const STEPS_DEFAULT_PAGE: u32 = 16;
+
+/// Resolve firmware-specific commands; `Ok(None)` defers to the shared resolver.
+pub fn resolve_fw(msg: &AtMessage<'_>) -> Result<Option<FwCommand>, HexaError> {
+    match (msg.name, msg.op) {
+        (b"FREQ", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            let StepParams {
+                freq,
+                time_ms,
+                phase,
+                glide,
+                colour,
+            } = parse_step_params(&mut params)?;
+            Ok(Some(FwCommand::Freq {
+                id: msg.id,
+                freq,
+                time_ms,
+                phase,
+                glide,
+                colour,
+            }))
+        }
+        (b"SWEEP", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            let start = parse_param_millihertz(params.next())?;
+            let end = parse_param_millihertz(params.next())?;
+            let time_ms = parse_param_u32(params.next())?;
+            let curve = match params.next().ok_or(HexaError::MissingParam)? {
+                b"LIN" => SweepCurve::Linear,
+                b"LOG" => SweepCurve::Logarithmic,
+                _ => return Err(HexaError::InvalidParam),
+            };
+            let update_ms = match params.next() {
+                Some(p) => parse_param_u32(Some(p))?,
+                None => SWEEP_DEFAULT_UPDATE_MS,
+            };
+            // A logarithmic sweep has no defined path through 0 Hz
+            let zero = MilliHertz(0);
+            if update_ms == 0
+                || (curve == SweepCurve::Logarithmic && (start == zero || end == zero))
+            {
+                return Err(HexaError::InvalidParam);
+            }
+            Ok(Some(FwCommand::Sweep {
+                id: msg.id,
+                start,
+                time_ms,
+                sweep: Sweep {
+                    end,
+                    curve,
+                    update_ms,
+                },
+            }))
+        }
+        (b"PHASE", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            let phase = parse_param_phase(params.next())?;
+            Ok(Some(FwCommand::Phase { id: msg.id, phase }))
+        }
+        (b"OPERATION", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            let sub = match params.next().ok_or(HexaError::MissingParam)? {
+                b"PREPARE" => OperationSub::Prepare {
+                    repeat: match params.next() {
+                        Some(p) => parse_param_u32(Some(p))?,
+                        None => 1,
+                    },
+                },
+                b"GENERATE" => {
+                    let (mode, slot, operation_id) = parse_generate_params(&mut params)?;
+                    OperationSub::Generate {
+                        mode,
+                        slot,
+                        operation_id,
+                    }
+                }
+                b"STREAM" => {
+                    let (mode, slot, operation_id) = parse_generate_params(&mut params)?;
+                    if slot.is_some() || operation_id.is_some() {
+                        return Err(HexaError::InvalidParam);
+                    }
+                    OperationSub::Stream { mode }
+                }
+                b"ARM" => {
+                    let delay_ms = parse_param_u32(params.next())?;
+                    let (mode, slot, operation_id) = parse_generate_params(&mut params)?;
+                    OperationSub::Arm {
+                        delay_ms,
+                        mode,
+                        slot,
+                        operation_id,
+                    }
+                }
+                b"SAVE" => OperationSub::Save,
+                b"LOAD" => OperationSub::Load,
+                b"STOP" => OperationSub::Stop,
+                b"PAUSE" => OperationSub::Pause,
+                b"RESUME" => OperationSub::Resume,
+                b"TIMING" => OperationSub::Timing {
+                    index: match params.next() {
+                        Some(p) => Some(parse_param_u32(Some(p))?),
+                        None => None,
+                    },
+                },
+                b"REPLACE" => OperationSub::Replace {
+                    target: parse_step_ref(&mut params)?,
+                    step: parse_step_params(&mut params)?,
+                },
+                b"INSERT" => OperationSub::Insert {
+                    target: parse_step_ref(&mut params)?,
+                    step: parse_step_params(&mut params)?,
+                },
+                b"DELETE" => OperationSub::Delete {
+                    target: parse_step_ref(&mut params)?,
+                },
+                b"TRUNCATE" => OperationSub::Truncate {
+                    len: parse_param_u32(params.next())?,
+                },
+                b"VALIDATE" => OperationSub::Validate {
+                    operation_id: match params.next() {
+                        Some(p) => Some(parse_param_u32(Some(p))?),
+                        None => None,
+                    },
+                },
+                b"INFO" => OperationSub::Info,
+                b"STEPS" => OperationSub::Steps {
+                    start: match params.next() {
+                        Some(p) => parse_param_u32(Some(p))?,
+                        None => 0,
+                    },
+                    count: match params.next() {
+                        Some(p) => parse_param_u32(Some(p))?,
+                        None => STEPS_DEFAULT_PAGE,
+                    },
+                },
+                _ => return Err(HexaError::InvalidParam),
+            };
+            Ok(Some(FwCommand::Operation { id: msg.id, sub }))
+        }
+        (b"SLOT", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            let sub = match params.next().ok_or(HexaError::MissingParam)? {
+                b"LIST" => SlotSub::List,
+                b"STORE" => {
+                    let slot = parse_param_slot(params.next())?;
+                    let name = params.next().ok_or(HexaError::MissingParam)?;
+                    if !is_valid_slot_name(name) {
+                        return Err(HexaError::InvalidParam);
+                    }
+                    let name = core::str::from_utf8(name).map_err(|_| HexaError::InvalidParam)?;
+                    SlotSub::Store {
+                        slot,
+                        name: SlotName::try_from(name).map_err(|_| HexaError::InvalidParam)?,
+                    }
+                }
+                b"LOAD" => SlotSub::Load {
+                    slot: parse_param_slot(params.next())?,
+                },
+                b"DELETE" => SlotSub::Delete {
+                    slot: parse_param_slot(params.next())?,
+                },
+                _ => return Err(HexaError::InvalidParam),
+            };
+            Ok(Some(FwCommand::Slot { id: msg.id, sub }))
+        }
+        (b"STREAM", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            if params.clone().next() == Some(b"END") {
+                return Ok(Some(FwCommand::StreamEnd { id: msg.id }));
+            }
+            let step = parse_step_params(&mut params)?;
+            Ok(Some(FwCommand::StreamStep { id: msg.id, step }))
+        }
+        (b"WAVEFORM", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            let bytes = params.next().ok_or(HexaError::MissingParam)?;
+            let waveform = Waveform::from_bytes(bytes).ok_or(HexaError::InvalidParam)?;
+            Ok(Some(FwCommand::Waveform {
+                id: msg.id,
+                waveform,
+            }))
+        }
+        (b"FREQINFO", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            let freq = parse_param_millihertz(params.next())?;
+            Ok(Some(FwCommand::FreqInfo { id: msg.id, freq }))
+        }
+        (b"CALIBRATE", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            let sub = match params.next().ok_or(HexaError::MissingParam)? {
+                b"PPB" => CalibrateSub::Offset {
+                    ppb: parse_param_i32(params.next())?,
+                },
+                // ppm with up to three decimals is a whole number of ppb
+                b"PPM" => CalibrateSub::Offset {
+                    ppb: parse_param_milli_i32(params.next())?,
+                },
+                b"MEASURED" => CalibrateSub::Measured {
+                    requested: parse_param_millihertz(params.next())?,
+                    measured: parse_param_millihertz(params.next())?,
+                },
+                _ => return Err(HexaError::InvalidParam),
+            };
+            if let CalibrateSub::Offset { ppb } = sub {
+                if ppb.unsigned_abs() > CAL_PPB_LIMIT as u32 {
+                    return Err(HexaError::InvalidParam);
+                }
+            }
+            Ok(Some(FwCommand::Calibrate { id: msg.id, sub }))
+        }
+        (b"CALIBRATE", AtOp::Query) => Ok(Some(FwCommand::CalibrateQuery)),
+        (b"AUTOSTART", AtOp::Set) => {
+            let mut params = msg.params.clone();
+            let enabled = match params.next().ok_or(HexaError::MissingParam)? {
+                b"ON" => true,
+                b"OFF" => false,
+                _ => return Err(HexaError::InvalidParam),
+            };
+            Ok(Some(FwCommand::Autostart {
+                id: msg.id,
+                enabled,
+            }))
+        }
+        (b"AUTOSTART", AtOp::Query) => Ok(Some(FwCommand::AutostartQuery)),
+        _ => Ok(None),
+    }
+}
+
+/// `[#CONTINUOUS|#RESET][#SLOT#n][#opId]`, as taken by GENERATE and ARM.
+fn parse_generate_params(
+    params: &mut Params<'_>,
+) -> Result<(StepMode, Option<u8>, Option<u32>), HexaError> {
+    let mut next = params.next();
+    let mode = match next {
+        Some(b"RESET") => StepMode::Reset,
+        _ => StepMode::Continuous,
+    };
+    if let Some(b"CONTINUOUS" | b"RESET") = next {
+        next = params.next();
+    }
+    let slot = match next {
+        Some(b"SLOT") => {
+            let slot = parse_param_slot(params.next())?;
+            next = params.next();
+            Some(slot)
+        }
+        _ => None,
+    };
+    let operation_id = match next {
+        Some(p) => Some(parse_param_u32(Some(p))?),
+        None => None,
+    };
+    Ok((mode, slot, operation_id))
+}
+
+/// `ID#n` or `INDEX#n`.
+fn parse_step_ref(params: &mut Params<'_>) -> Result<StepRef, HexaError> {
+    match params.next().ok_or(HexaError::MissingParam)? {
+        b"ID" => Ok(StepRef::Id(parse_param_u32(params.next())?)),
+        b"INDEX" => Ok(StepRef::Index(parse_param_u32(params.next())?)),
+        _ => Err(HexaError::InvalidParam),
+    }
+}
+
+/// `freq#timeMs[#phase][#GLIDE#glideMs[#LIN|EXP]][#RGB#r#g#b]`, as taken by FREQ.
+fn parse_step_params(params: &mut Params<'_>) -> Result<StepParams, HexaError> {
+    let freq = parse_param_millihertz(params.next())?;
+    let time_ms = parse_param_u32(params.next())?;
+    let mut next = params.next();
+    let phase = match next {
+        Some(p) if p != b"GLIDE" && p != b"RGB" => {
+            next = params.next();
+            Some(parse_param_phase(Some(p))?)
+        }
+        _ => None,
+    };
+    let glide = match next {
+        Some(b"GLIDE") => {
+            let glide_ms = parse_param_u32(params.next())?;
+            next = params.next();
+            let curve = match next {
+                Some(b"EXP") => SweepCurve::Logarithmic,
+                _ => SweepCurve::Linear,
+            };
+            if let Some(b"LIN" | b"EXP") = next {
+                next = params.next();
+            }
+            // The glide is part of the step time
+            if glide_ms > time_ms {
+                return Err(HexaError::InvalidParam);
+            }
+            Some(Glide {
+                time_ms: glide_ms,
+                curve,
+            })
+        }
+        _ => None,
+    };
+    let colour = match next {
+        None => None,
+        Some(b"RGB") => Some(Rgb {
+            r: parse_param_u8(params.next())?,
+            g: parse_param_u8(params.next())?,
+            b: parse_param_u8(params.next())?,
+        }),
+        Some(_) => return Err(HexaError::InvalidParam),
+    };
+    if colour.is_some() && params.next().is_some() {
+        return Err(HexaError::InvalidParam);
+    }
+    Ok(StepParams {
+        freq,
+        time_ms,
+        phase,
+        glide,
+        colour,
+    })
+}
+
+pub fn parse_param_u32(param: Option<&[u8]>) -> Result<u32, HexaError> {
+    let bytes = param.ok_or(HexaError::MissingParam)?;
+    if bytes.is_empty() {
+        return Err(HexaError::InvalidParam);
+    }
+    let mut val: u32 = 0;
+    for &b in bytes {
+        if !b.is_ascii_digit() {
+            return Err(HexaError::InvalidParam);
+        }
+        val = val
+            .checked_mul(10)
+            .and_then(|v| v.checked_add((b - b'0') as u32))
+            .ok_or(HexaError::InvalidParam)?;
+    }
+    Ok(val)
+}
+
+pub fn parse_param_u8(param: Option<&[u8]>) -> Result<u8, HexaError> {
+    u8::try_from(parse_param_u32(param)?).map_err(|_| HexaError::InvalidParam)
+}
+
+/// Parse a signed decimal integer such as `-1250` or `+40`.
+pub fn parse_param_i32(param: Option<&[u8]>) -> Result<i32, HexaError> {
+    let (negative, bytes) = split_sign(param.ok_or(HexaError::MissingParam)?);
+    let val = parse_param_u32(Some(bytes))?;
+    apply_sign(negative, val as u64)
+}
+
+/// Parse a signed decimal with up to three decimals, scaled by 1000 (`-1.5` -> `-1500`).
+pub fn parse_param_milli_i32(param: Option<&[u8]>) -> Result<i32, HexaError> {
+    let (negative, bytes) = split_sign(param.ok_or(HexaError::MissingParam)?);
+    let val = parse_param_millihertz(Some(bytes))?;
+    apply_sign(negative, val.0)
+}
+
+fn split_sign(bytes: &[u8]) -> (bool, &[u8]) {
+    match bytes.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        Some((b'+', rest)) => (false, rest),
+        _ => (false, bytes),
+    }
+}
+
+fn apply_sign(negative: bool, magnitude: u64) -> Result<i32, HexaError> {
+    let val = if negative {
+        -(magnitude as i64)
+    } else {
+        magnitude as i64
+    };
+    i32::try_from(val).map_err(|_| HexaError::InvalidParam)
+}
+
+/// Operation slot number, `0..SLOT_COUNT`.
+pub fn parse_param_slot(param: Option<&[u8]>) -> Result<u8, HexaError> {
+    let slot = parse_param_u32(param)?;
+    if slot >= SLOT_COUNT as u32 {
+        return Err(HexaError::InvalidParam);
+    }
+    Ok(slot as u8)
+}
+
+/// Parse a phase offset in 11.25 degree steps (0-31).
+pub fn parse_param_phase(param: Option<&[u8]>) -> Result<u8, HexaError> {
+    let phase = parse_param_u32(param)?;
+    if phase >= PHASE_STEPS as u32 {
+        return Err(HexaError::InvalidParam);
+    }
+    Ok(phase as u8)
+}
+
+/// Parse a decimal frequency in Hz such as `440`, `7.83` or `432.081`.
+pub fn parse_param_millihertz(param: Option<&[u8]>) -> Result<MilliHertz, HexaError> {
+    let bytes = param.ok_or(HexaError::MissingParam)?;
+    let (int_part, frac_part) = match bytes.iter().position(|&b| b == b'.') {
+        // A point must be followed by at least one decimal
+        Some(pos) if pos + 1 == bytes.len() => return Err(HexaError::InvalidParam),
+        Some(pos) => (&bytes[..pos], &bytes[pos + 1..]),
+        None => (bytes, &[] as &[u8]),
+    };
+    if int_part.is_empty() || frac_part.len() > 3 {
+        return Err(HexaError::InvalidParam);
+    }
+
+    let hz = parse_param_u32(Some(int_part))?;
+    let mut frac: u64 = 0;
+    for i in 0..3 {
+        let digit = match frac_part.get(i) {
+            Some(b) if b.is_ascii_digit() => (b - b'0') as u64,
+            Some(_) => return Err(HexaError::InvalidParam),
+            None => 0,
+        };
+        frac = frac * 10 + digit;
+    }
+    Ok(MilliHertz(hz as u64 * 1000 + frac))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::at::dispatch_at_payload;
+    use crate::error::FirmwareError;
+
+    fn operation_sub(payload: &[u8]) -> Result<OperationSub, FirmwareError> {
+        match dispatch_at_payload(payload)? {
+            Command::Fw(FwCommand::Operation { sub, .. }) => Ok(sub),
+            _ => panic!("not an OPERATION command"),
+        }
+    }
+
+    fn fw_command(payload: &[u8]) -> Result<FwCommand, FirmwareError> {
+        match dispatch_at_payload(payload)? {
+            Command::Fw(cmd) => Ok(cmd),
+            _ => panic!("not a firmware command"),
+        }
+    }
+
+    /// Every one of `payloads` fails to parse.
+    fn all_rejected(payloads: &[&[u8]]) {
+        for payload in payloads {
+            assert!(
+                dispatch_at_payload(payload).is_err(),
+                "{}",
+                core::str::from_utf8(payload).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn generate_takes_an_operation_id() {
+        assert!(
+            operation_sub(b"AT+OPERATION=3#GENERATE#RESET#SLOT#2#7").unwrap()
+                == OperationSub::Generate {
+                    mode: StepMode::Reset,
+                    slot: Some(2),
+                    operation_id: Some(7),
+                }
+        );
+        assert!(
+            operation_sub(b"AT+OPERATION=3#GENERATE#7").unwrap()
+                == OperationSub::Generate {
+                    mode: StepMode::Continuous,
+                    slot: None,
+                    operation_id: Some(7),
+                }
+        );
+        assert!(
+            operation_sub(b"AT+OPERATION=3#GENERATE").unwrap()
+                == OperationSub::Generate {
+                    mode: StepMode::Continuous,
+                    slot: None,
+                    operation_id: None,
+                }
+        );
+    }
+
+    #[test]
+    fn arm_takes_an_operation_id() {
+        assert!(
+            operation_sub(b"AT+OPERATION=3#ARM#500#CONTINUOUS#7").unwrap()
+                == OperationSub::Arm {
+                    delay_ms: 500,
+                    mode: StepMode::Continuous,
+                    slot: None,
+                    operation_id: Some(7),
+                }
+        );
+    }
+
+    #[test]
+    fn stream_refuses_an_operation_id() {
+        assert!(operation_sub(b"AT+OPERATION=3#STREAM#7").is_err());
+        assert!(operation_sub(b"AT+OPERATION=3#GENERATE#ON").is_err());
+    }
+
+    #[test]
+    fn steps_pages_from_the_start_by_default() {
+        assert!(operation_sub(b"AT+OPERATION=3#INFO").unwrap() == OperationSub::Info);
+        assert!(
+            operation_sub(b"AT+OPERATION=3#STEPS").unwrap()
+                == OperationSub::Steps {
+                    start: 0,
+                    count: STEPS_DEFAULT_PAGE,
+                }
+        );
+        assert!(
+            operation_sub(b"AT+OPERATION=3#STEPS#32").unwrap()
+                == OperationSub::Steps {
+                    start: 32,
+                    count: STEPS_DEFAULT_PAGE,
+                }
+        );
+        assert!(
+            operation_sub(b"AT+OPERATION=3#STEPS#4#2").unwrap()
+                == OperationSub::Steps { start: 4, count: 2 }
+        );
+        assert!(operation_sub(b"AT+OPERATION=3#STEPS#-1").is_err());
+        assert!(operation_sub(b"AT+OPERATION=3#STEPS#4#x").is_err());
+    }
+
+    #[test]
+    fn step_edits_take_an_id_or_an_index() {
+        let params = |freq, time_ms, phase| StepParams {
+            freq: MilliHertz(freq),
+            time_ms,
+            phase,
+            glide: None,
+            colour: None,
+        };
+        assert!(
+            operation_sub(b"AT+OPERATION=3#REPLACE#ID#7#440.5#100").unwrap()
+                == OperationSub::Replace {
+                    target: StepRef::Id(7),
+                    step: params(440_500, 100, None),
+                }
+        );
+        assert!(
+            operation_sub(b"AT+OPERATION=3#INSERT#INDEX#0#1000#20#8").unwrap()
+                == OperationSub::Insert {
+                    target: StepRef::Index(0),
+                    step: params(1_000_000, 20, Some(8)),
+                }
+        );
+        assert!(
+            operation_sub(b"AT+OPERATION=3#DELETE#INDEX#2").unwrap()
+                == OperationSub::Delete {
+                    target: StepRef::Index(2),
+                }
+        );
+        assert!(
+            operation_sub(b"AT+OPERATION=3#TRUNCATE#0").unwrap()
+                == OperationSub::Truncate { len: 0 }
+        );
+    }
+
+    #[test]
+    fn step_edits_reject_malformed_input() {
+        for payload in [
+            &b"AT+OPERATION=3#REPLACE"[..],
+            b"AT+OPERATION=3#REPLACE#ID",
+            b"AT+OPERATION=3#REPLACE#ID#7",
+            b"AT+OPERATION=3#REPLACE#ID#7#440",
+            b"AT+OPERATION=3#REPLACE#STEP#7#440#100",
+            b"AT+OPERATION=3#INSERT#INDEX#x#440#100",
+            b"AT+OPERATION=3#INSERT#INDEX#0#440#100#32",
+            b"AT+OPERATION=3#INSERT#INDEX#0#440#100#8#9",
+            b"AT+OPERATION=3#DELETE",
+            b"AT+OPERATION=3#DELETE#7",
+            b"AT+OPERATION=3#TRUNCATE",
+            b"AT+OPERATION=3#TRUNCATE#-1",
+        ] {
+            assert!(
+                operation_sub(payload).is_err(),
+                "{}",
+                core::str::from_utf8(payload).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn validate_takes_an_optional_operation_id() {
+        assert!(
+            operation_sub(b"AT+OPERATION=3#VALIDATE").unwrap()
+                == OperationSub::Validate { operation_id: None }
+        );
+        assert!(
+            operation_sub(b"AT+OPERATION=3#VALIDATE#7").unwrap()
+                == OperationSub::Validate {
+                    operation_id: Some(7),
+                }
+        );
+        assert!(operation_sub(b"AT+OPERATION=3#VALIDATE#ALL").is_err());
+    }
+
+    #[test]
+    fn sweep_takes_a_curve_and_an_update_interval() {
+        assert!(matches!(
+            fw_command(b"AT+SWEEP=1#100#1000.5#500#LOG#5"),
+            Ok(FwCommand::Sweep {
+                id: 1,
+                start: MilliHertz(100_000),
+                time_ms: 500,
+                sweep: Sweep {
+                    end: MilliHertz(1_000_500),
+                    curve: SweepCurve::Logarithmic,
+                    update_ms: 5,
+                },
+            })
+        ));
+        // A linear sweep may start from 0 Hz
+        assert!(matches!(
+            fw_command(b"AT+SWEEP=1#0#1000#500#LIN"),
+            Ok(FwCommand::Sweep {
+                start: MilliHertz(0),
+                sweep: Sweep {
+                    curve: SweepCurve::Linear,
+                    update_ms: SWEEP_DEFAULT_UPDATE_MS,
+                    ..
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn sweep_rejects_malformed_input() {
+        all_rejected(&[
+            b"AT+SWEEP=1#100#1000#500",
+            b"AT+SWEEP=1#100#1000#500#EXP",
+            b"AT+SWEEP=1#100#1000#500#LIN#0",
+            b"AT+SWEEP=1#100#1000#500#LIN#x",
+            b"AT+SWEEP=1#0#1000#500#LOG",
+            b"AT+SWEEP=1#100#0#500#LOG",
+            b"AT+SWEEP=1#100#1000",
+            b"AT+SWEEP=1#100#1000.0001#500#LIN",
+            b"AT+SWEEP=1#-100#1000#500#LIN",
+        ]);
+    }
+
+    #[test]
+    fn calibrate_takes_ppb_ppm_or_a_measurement() {
+        let offset = |payload| match fw_command(payload) {
+            Ok(FwCommand::Calibrate {
+                id: 2,
+                sub: CalibrateSub::Offset { ppb },
+            }) => ppb,
+            _ => panic!("not a calibration offset"),
+        };
+        assert_eq!(offset(b"AT+CALIBRATE=2#PPB#-1500"), -1500);
+        assert_eq!(offset(b"AT+CALIBRATE=2#PPM#1.5"), 1500);
+        assert_eq!(offset(b"AT+CALIBRATE=2#PPM#-0.001"), -1);
+        assert_eq!(offset(b"AT+CALIBRATE=2#PPB#1000000"), CAL_PPB_LIMIT);
+        assert_eq!(offset(b"AT+CALIBRATE=2#PPM#-1000"), -CAL_PPB_LIMIT);
+
+        assert!(matches!(
+            fw_command(b"AT+CALIBRATE=2#MEASURED#1000#1000.001"),
+            Ok(FwCommand::Calibrate {
+                id: 2,
+                sub: CalibrateSub::Measured {
+                    requested: MilliHertz(1_000_000),
+                    measured: MilliHertz(1_000_001),
+                },
+            })
+        ));
+        assert!(matches!(
+            fw_command(b"AT+CALIBRATE?"),
+            Ok(FwCommand::CalibrateQuery)
+        ));
+    }
+
+    #[test]
+    fn calibrate_rejects_malformed_input() {
+        all_rejected(&[
+            b"AT+CALIBRATE=2",
+            b"AT+CALIBRATE=2#PPT#5",
+            b"AT+CALIBRATE=2#PPB",
+            b"AT+CALIBRATE=2#PPB#1.5",
+            b"AT+CALIBRATE=2#PPB#1000001",
+            b"AT+CALIBRATE=2#PPM#-1000.001",
+            b"AT+CALIBRATE=2#PPM#1.0001",
+            b"AT+CALIBRATE=2#PPM#x",
+            b"AT+CALIBRATE=2#MEASURED#1000",
+            b"AT+CALIBRATE=2#MEASURED#1000#-1",
+        ]);
+    }
+
+    #[test]
+    fn slot_sub_commands_parse() {
+        let slot_sub = |payload| match fw_command(payload) {
+            Ok(FwCommand::Slot { id: 5, sub }) => sub,
+            _ => panic!("not a SLOT command"),
+        };
+        assert!(slot_sub(b"AT+SLOT=5#LIST") == SlotSub::List);
+        assert!(
+            slot_sub(b"AT+SLOT=5#STORE#7#warm-up_1.a")
+                == SlotSub::Store {
+                    slot: 7,
+                    name: SlotName::try_from("warm-up_1.a").unwrap(),
+                }
+        );
+        assert!(slot_sub(b"AT+SLOT=5#LOAD#0") == SlotSub::Load { slot: 0 });
+        assert!(slot_sub(b"AT+SLOT=5#DELETE#3") == SlotSub::Delete { slot: 3 });
+        assert!(
+            operation_sub(b"AT+OPERATION=5#GENERATE#SLOT#1").unwrap()
+                == OperationSub::Generate {
+                    mode: StepMode::Continuous,
+                    slot: Some(1),
+                    operation_id: None,
+                }
+        );
+    }
+
+    #[test]
+    fn slot_sub_commands_reject_malformed_input() {
+        all_rejected(&[
+            b"AT+SLOT=5",
+            b"AT+SLOT=5#SAVE#0",
+            b"AT+SLOT=5#STORE#8#name",
+            b"AT+SLOT=5#STORE#0",
+            b"AT+SLOT=5#STORE#0#a,b",
+            b"AT+SLOT=5#STORE#0#0123456789abcdefg",
+            b"AT+SLOT=5#LOAD",
+            b"AT+SLOT=5#LOAD#x",
+            b"AT+SLOT=5#DELETE#255",
+            b"AT+OPERATION=5#GENERATE#SLOT",
+            b"AT+OPERATION=5#GENERATE#SLOT#8",
+        ]);
+    }
+
+    #[test]
+    fn stream_takes_steps_and_an_end() {
+        assert!(matches!(
+            fw_command(b"AT+STREAM=4#440.5#100"),
+            Ok(FwCommand::StreamStep {
+                id: 4,
+                step: StepParams {
+                    freq: MilliHertz(440_500),
+                    time_ms: 100,
+                    phase: None,
+                    ..
+                },
+            })
+        ));
+        assert!(matches!(
+            fw_command(b"AT+STREAM=4#440#100#31"),
+            Ok(FwCommand::StreamStep {
+                step: StepParams {
+                    phase: Some(31),
+                    ..
+                },
+                ..
+            })
+        ));
+        assert!(matches!(
+            fw_command(b"AT+STREAM=4#END"),
+            Ok(FwCommand::StreamEnd { id: 4 })
+        ));
+        assert!(
+            operation_sub(b"AT+OPERATION=3#STREAM#RESET").unwrap()
+                == OperationSub::Stream {
+                    mode: StepMode::Reset,
+                }
+        );
+    }
+
+    #[test]
+    fn stream_rejects_malformed_input() {
+        all_rejected(&[
+            b"AT+STREAM=4",
+            b"AT+STREAM=4#440",
+            b"AT+STREAM=4#440#x",
+            b"AT+STREAM=4#440#100#32",
+            b"AT+STREAM=4#440#100#1#2",
+            b"AT+STREAM=4#FINISH",
+            b"AT+OPERATION=3#STREAM#SLOT#1",
+        ]);
+    }
+
+    #[test]
+    fn freq_takes_a_glide_after_the_phase() {
+        let glide = |payload| match fw_command(payload) {
+            Ok(FwCommand::Freq { phase, glide, .. }) => (phase, glide),
+            _ => panic!("not a FREQ command"),
+        };
+        let linear = |time_ms| Glide {
+            time_ms,
+            curve: SweepCurve::Linear,
+        };
+        assert!(glide(b"AT+FREQ=1#440#100#GLIDE#20") == (None, Some(linear(20))));
+        assert!(glide(b"AT+FREQ=1#440#100#8#GLIDE#100#LIN") == (Some(8), Some(linear(100))));
+        assert!(
+            glide(b"AT+FREQ=1#440#100#GLIDE#0#EXP")
+                == (
+                    None,
+                    Some(Glide {
+                        time_ms: 0,
+                        curve: SweepCurve::Logarithmic,
+                    })
+                )
+        );
+        // REPLACE, INSERT and STREAM take the same step grammar
+        assert!(matches!(
+            fw_command(b"AT+STREAM=1#440#100#GLIDE#20#EXP"),
+            Ok(FwCommand::StreamStep {
+                step: StepParams {
+                    glide: Some(Glide {
+                        time_ms: 20,
+                        curve: SweepCurve::Logarithmic,
+                    }),
+                    ..
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn glide_rejects_malformed_input() {
+        all_rejected(&[
+            b"AT+FREQ=1#440#100#GLIDE",
+            b"AT+FREQ=1#440#100#GLIDE#x",
+            b"AT+FREQ=1#440#100#GLIDE#101",
+            b"AT+FREQ=1#440#100#GLIDE#20#LOG",
+            b"AT+FREQ=1#440#100#GLIDE#20#EXP#LIN",
+            b"AT+FREQ=1#440#100#GLIDE#20#8",
+            b"AT+OPERATION=3#REPLACE#ID#7#440#100#GLIDE#200",
+        ]);
+    }
+
+    #[test]
+    fn freq_takes_a_colour_last() {
+        let colour = |payload| match fw_command(payload) {
+            Ok(FwCommand::Freq {
+                phase,
+                glide,
+                colour,
+                ..
+            }) => (phase, glide.is_some(), colour),
+            _ => panic!("not a FREQ command"),
+        };
+        let rgb = Some(Rgb {
+            r: 255,
+            g: 128,
+            b: 0,
+        });
+        assert!(colour(b"AT+FREQ=1#440#100#RGB#255#128#0") == (None, false, rgb));
+        assert!(colour(b"AT+FREQ=1#440#100#8#RGB#255#128#0") == (Some(8), false, rgb));
+        assert!(colour(b"AT+FREQ=1#440#100#8#GLIDE#20#EXP#RGB#255#128#0") == (Some(8), true, rgb));
+        assert!(colour(b"AT+FREQ=1#440#100#GLIDE#20#RGB#255#128#0") == (None, true, rgb));
+        assert!(matches!(
+            operation_sub(b"AT+OPERATION=3#INSERT#INDEX#0#440#100#RGB#0#0#1"),
+            Ok(OperationSub::Insert {
+                step: StepParams {
+                    colour: Some(Rgb { r: 0, g: 0, b: 1 }),
+                    ..
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn colour_rejects_malformed_input() {
+        all_rejected(&[
+            b"AT+FREQ=1#440#100#RGB",
+            b"AT+FREQ=1#440#100#RGB#255#128",
+            b"AT+FREQ=1#440#100#RGB#256#0#0",
+            b"AT+FREQ=1#440#100#RGB#0#-1#0",
+            b"AT+FREQ=1#440#100#RGB#0#0#0#0",
+            // The colour comes after the glide
+            b"AT+FREQ=1#440#100#RGB#0#0#0#GLIDE#20",
+            b"AT+STREAM=1#440#100#RGB#x#0#0",
+        ]);
+    }
+
+    #[test]
+    fn millihertz_needs_a_decimal_after_the_point() {
+        assert_eq!(
+            parse_param_millihertz(Some(b"440")),
+            Ok(MilliHertz(440_000))
+        );
+        assert_eq!(parse_param_millihertz(Some(b"7.83")), Ok(MilliHertz(7_830)));
+        assert_eq!(
+            parse_param_millihertz(Some(b"432.081")),
+            Ok(MilliHertz(432_081))
+        );
+        assert!(parse_param_millihertz(Some(b"440.")).is_err());
+        assert!(parse_param_millihertz(Some(b".5")).is_err());
+        assert!(parse_param_millihertz(Some(b"1.0001")).is_err());
+    }
+}
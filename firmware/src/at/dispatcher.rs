@@ -2,34 +2,50 @@
 // SPDX-License-Identifier: MIT
 
 use hexa_tune_proto::at::{self, AtOp};
-use hexa_tune_proto_embedded::command::HexaCommand;
 use hexa_tune_proto_embedded::dispatch::resolve;
 
-use crate::channel::MsgString;
-use crate::error::FirmwareError;
+use crate::at::{Command, resolve_fw};
+use crate::channel::{MSG_LEN, Msg, MsgString};
+use crate::dds::MilliHertz;
+use crate::error::{FirmwareError, ProtoError};
 
-/// Parse an AT payload and resolve it to a typed HexaCommand.
-pub fn dispatch_at_payload(payload: &[u8]) -> Result<HexaCommand, FirmwareError> {
+/// Parse an AT payload and resolve it to a typed command.
+///
+/// Firmware-specific commands are tried first, everything else is resolved
+/// as a `HexaCommand`.
+pub fn dispatch_at_payload(payload: &[u8]) -> Result<Command, FirmwareError> {
     let msg = at::parse(payload).map_err(FirmwareError::Proto)?;
-    resolve(&msg).map_err(FirmwareError::Hexa)
+    if let Some(cmd) = resolve_fw(&msg).map_err(FirmwareError::Hexa)? {
+        return Ok(Command::Fw(cmd));
+    }
+    resolve(&msg)
+        .map(Command::Hexa)
+        .map_err(FirmwareError::Hexa)
+}
+
+/// Encode an AT response (name=id#params...) into a MsgString, failing with
+/// `ProtoError::BufferTooSmall` when the line is longer than `MSG_LEN`.
+pub fn encode_response(name: &[u8], id: u32, params: &[&[u8]]) -> Result<MsgString, FirmwareError> {
+    let mut buf = [0u8; MSG_LEN];
+    let n = at::encode(name, id, AtOp::Response, params, &mut buf)?;
+    let s = core::str::from_utf8(&buf[..n]).map_err(|_| ProtoError::InvalidUtf8)?;
+    MsgString::try_from(s).map_err(|_| FirmwareError::Proto(ProtoError::BufferTooSmall))
 }
 
-/// Encode an AT response (name=id#params...) into a MsgString.
-pub fn encode_response(name: &[u8], id: u32, params: &[&[u8]]) -> MsgString {
-    let mut buf = [0u8; 64];
-    if let Ok(n) = at::encode(name, id, AtOp::Response, params, &mut buf) {
-        if let Ok(s) = core::str::from_utf8(&buf[..n]) {
-            if let Ok(line) = MsgString::try_from(s) {
-                return line;
-            }
-        }
+/// `line` as the response to command `id`, its error when it could not be built.
+pub fn reply(id: u32, line: Result<MsgString, FirmwareError>) -> Msg {
+    match line {
+        Ok(line) => Msg::AtCmdResponse(line),
+        Err(e) => Msg::Err(id, e),
     }
-    MsgString::new()
 }
 
+// AT+DONE and AT+ERROR lines are at most `AT+ERROR=4294967295#255`
+const _: () = core::assert!(MSG_LEN >= 23);
+
 /// Encode an AT+DONE=id response.
 pub fn encode_done(id: u32) -> MsgString {
-    encode_response(b"DONE", id, &[])
+    encode_response(b"DONE", id, &[]).unwrap_or_default()
 }
 
 /// Encode an AT+ERROR=id#code response using u8 error code.
@@ -37,7 +53,7 @@ pub fn encode_error_response(id: u32, e: &FirmwareError) -> MsgString {
     let code = e.error_code();
     let mut code_buf = [0u8; 3];
     let code_len = u8_to_ascii(code, &mut code_buf);
-    encode_response(b"ERROR", id, &[&code_buf[..code_len]])
+    encode_response(b"ERROR", id, &[&code_buf[..code_len]]).unwrap_or_default()
 }
 
 /// Convert a u32 value to ASCII decimal bytes in a buffer.
@@ -59,6 +75,57 @@ pub fn u32_to_ascii_buf(val: u32, buf: &mut [u8; 10]) -> usize {
     len
 }
 
+/// Convert a u64 value to ASCII decimal bytes in a buffer.
+pub fn u64_to_ascii_buf(val: u64, buf: &mut [u8; 20]) -> usize {
+    if val == 0 {
+        buf[0] = b'0';
+        return 1;
+    }
+    let mut tmp = [0u8; 20];
+    let mut n = val;
+    let mut i = 20usize;
+    while n > 0 {
+        i -= 1;
+        tmp[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+    }
+    let len = 20 - i;
+    buf[..len].copy_from_slice(&tmp[i..]);
+    len
+}
+
+/// Convert an i32 value to ASCII decimal bytes with a leading '-' when negative.
+pub fn i32_to_ascii_buf(val: i32, buf: &mut [u8; 11]) -> usize {
+    let mut digits = [0u8; 10];
+    let len = u32_to_ascii_buf(val.unsigned_abs(), &mut digits);
+    let sign = usize::from(val < 0);
+    buf[0] = b'-';
+    buf[sign..sign + len].copy_from_slice(&digits[..len]);
+    sign + len
+}
+
+/// Convert a frequency to ASCII decimal Hz, with the fraction only when non-zero.
+pub fn millihertz_to_ascii_buf(freq: MilliHertz, buf: &mut [u8; 24]) -> usize {
+    let mut int_buf = [0u8; 10];
+    let int_len = u32_to_ascii_buf(freq.hz(), &mut int_buf);
+    buf[..int_len].copy_from_slice(&int_buf[..int_len]);
+
+    let mut frac = freq.frac_millihertz();
+    if frac == 0 {
+        return int_len;
+    }
+    buf[int_len] = b'.';
+    let mut len = int_len + 1;
+    let mut scale = 100;
+    while frac > 0 {
+        buf[len] = b'0' + (frac / scale) as u8;
+        frac %= scale;
+        scale /= 10;
+        len += 1;
+    }
+    len
+}
+
 fn u8_to_ascii(val: u8, buf: &mut [u8; 3]) -> usize {
     if val >= 100 {
         buf[0] = b'0' + val / 100;
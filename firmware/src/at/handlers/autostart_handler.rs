@@ -0,0 +1,37 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use defmt::{error, info};
+
+use crate::AT_CH;
+use crate::at::{encode_response, reply};
+use crate::channel::*;
+use crate::storage::{load_config, update_config};
+
+fn on_off(enabled: bool) -> &'static [u8] {
+    if enabled { b"ON" } else { b"OFF" }
+}
+
+#[embassy_executor::task]
+pub async fn autostart_task(id: u32, enabled: bool) {
+    info!("Storing AUTOSTART {}", enabled);
+    match update_config(|config| config.autostart = enabled).await {
+        Ok(_) => {
+            // Build completed response: AT+AUTOSTART=id#ON|OFF#COMPLETED
+            let completed = encode_response(b"AUTOSTART", id, &[on_off(enabled), b"COMPLETED"]);
+            AT_CH.send(reply(id, completed)).await;
+        }
+        Err(e) => {
+            error!("Failed to store AUTOSTART");
+            AT_CH.send(Msg::Err(id, e)).await;
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn autostart_query_task() {
+    let enabled = load_config().await.is_some_and(|config| config.autostart);
+    // Build response: AT+AUTOSTART=0#ON|OFF
+    let response = encode_response(b"AUTOSTART", 0, &[on_off(enabled)]);
+    AT_CH.send(reply(0, response)).await;
+}
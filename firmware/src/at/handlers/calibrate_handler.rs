@@ -0,0 +1,20 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use defmt::info;
+
+use crate::DDS_CH;
+use crate::at::CalibrateSub;
+use crate::channel::*;
+
+#[embassy_executor::task]
+pub async fn calibrate_task(id: u32, sub: CalibrateSub) {
+    info!("Sending CALIBRATE command to DDS task");
+    DDS_CH.send(Msg::Calibrate { id, sub }).await;
+}
+
+#[embassy_executor::task]
+pub async fn calibrate_query_task() {
+    info!("Sending CALIBRATE query to DDS task");
+    DDS_CH.send(Msg::CalibrateQuery).await;
+}
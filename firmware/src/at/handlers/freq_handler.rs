@@ -5,10 +5,58 @@ use defmt::info;
 
 use crate::DDS_CH;
 use crate::channel::*;
+use crate::dds::{Glide, MilliHertz, Rgb, Sweep, Waveform};
 
 #[embassy_executor::task]
-pub async fn freq_task(id: u32, freq: u32, time_ms: u32) {
+pub async fn freq_task(
+    id: u32,
+    freq: MilliHertz,
+    time_ms: u32,
+    phase: Option<u8>,
+    glide: Option<Glide>,
+    colour: Option<Rgb>,
+) {
     info!("Sending FREQ command to DDS task");
-    DDS_CH.send(Msg::FreqSet { id, freq, time_ms }).await;
+    DDS_CH
+        .send(Msg::FreqSet {
+            id,
+            freq,
+            time_ms,
+            phase,
+            glide,
+            colour,
+        })
+        .await;
     info!("FREQ command sent to DDS task");
 }
+
+#[embassy_executor::task]
+pub async fn freq_info_task(id: u32, freq: MilliHertz) {
+    info!("Sending FREQINFO query to DDS task");
+    DDS_CH.send(Msg::FreqInfo { id, freq }).await;
+}
+
+#[embassy_executor::task]
+pub async fn phase_task(id: u32, phase: u8) {
+    info!("Sending PHASE command to DDS task");
+    DDS_CH.send(Msg::PhaseSet { id, phase }).await;
+}
+
+#[embassy_executor::task]
+pub async fn waveform_task(id: u32, waveform: Waveform) {
+    info!("Sending WAVEFORM command to DDS task");
+    DDS_CH.send(Msg::WaveformSet { id, waveform }).await;
+}
+
+#[embassy_executor::task]
+pub async fn sweep_task(id: u32, start: MilliHertz, time_ms: u32, sweep: Sweep) {
+    info!("Sending SWEEP command to DDS task");
+    DDS_CH
+        .send(Msg::SweepSet {
+            id,
+            start,
+            time_ms,
+            sweep,
+        })
+        .await;
+}
@@ -5,9 +5,19 @@ mod version_handler;
 pub use version_handler::*;
 mod setrgb_handler;
 pub use setrgb_handler::*;
+#[cfg(target_os = "none")]
 mod fwupdate_handler;
+#[cfg(target_os = "none")]
 pub use fwupdate_handler::*;
 mod freq_handler;
 pub use freq_handler::*;
 mod operation_handler;
 pub use operation_handler::*;
+mod calibrate_handler;
+pub use calibrate_handler::*;
+mod autostart_handler;
+pub use autostart_handler::*;
+mod slot_handler;
+pub use slot_handler::*;
+mod stream_handler;
+pub use stream_handler::*;
@@ -3,10 +3,9 @@
 
 use defmt::info;
 
-use hexa_tune_proto_embedded::command::OperationSub;
-
 use crate::AT_CH;
 use crate::DDS_CH;
+use crate::at::OperationSub;
 use crate::channel::*;
 
 #[embassy_executor::task]
@@ -21,3 +20,9 @@ pub async fn operation_status_task() {
     info!("Requesting OPERATION status");
     AT_CH.send(Msg::GetOperationStatus).await;
 }
+
+#[embassy_executor::task]
+pub async fn bulk_task(id: u32) {
+    info!("Sending bulk chunk to DDS task");
+    DDS_CH.send(Msg::BulkChunk(id)).await;
+}
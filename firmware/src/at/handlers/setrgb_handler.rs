@@ -6,5 +6,12 @@ use crate::channel::*;
 
 #[embassy_executor::task]
 pub async fn setrgb_task(id: u32, r: u8, g: u8, b: u8) {
-    RGB_CH.send(Msg::RgbSet { id, r, g, b }).await;
+    RGB_CH
+        .send(Msg::RgbSet {
+            id: Some(id),
+            r,
+            g,
+            b,
+        })
+        .await;
 }
@@ -0,0 +1,14 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use defmt::info;
+
+use crate::DDS_CH;
+use crate::at::SlotSub;
+use crate::channel::*;
+
+#[embassy_executor::task]
+pub async fn slot_task(id: u32, sub: SlotSub) {
+    info!("Sending SLOT command to DDS task");
+    DDS_CH.send(Msg::SlotCmd { id, sub }).await;
+}
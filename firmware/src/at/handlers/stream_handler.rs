@@ -0,0 +1,53 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use defmt::{error, info};
+
+use crate::AT_CH;
+use crate::at::{StepParams, encode_response, reply, u32_to_ascii_buf};
+use crate::channel::*;
+use crate::dds::{FreqStep, StreamItem, stream_push};
+
+// The host sends steps back to back, leave room for a few in flight
+#[embassy_executor::task(pool_size = 4)]
+pub async fn stream_step_task(id: u32, step: StepParams) {
+    let item = StreamItem::Step(FreqStep {
+        id,
+        freq: step.freq,
+        time_ms: step.time_ms,
+        phase: step.phase,
+        sweep: None,
+        glide: step.glide,
+        colour: step.colour,
+    });
+    match stream_push(item) {
+        Ok(credit) => {
+            // Build completed response: AT+STREAM=id#credit#COMPLETED
+            let mut credit_buf = [0u8; 10];
+            let credit_len = u32_to_ascii_buf(credit as u32, &mut credit_buf);
+            let completed =
+                encode_response(b"STREAM", id, &[&credit_buf[..credit_len], b"COMPLETED"]);
+            AT_CH.send(reply(id, completed)).await;
+        }
+        Err(e) => {
+            error!("Stream step rejected");
+            AT_CH.send(Msg::Err(id, e)).await;
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn stream_end_task(id: u32) {
+    info!("Queueing STREAM end");
+    match stream_push(StreamItem::End) {
+        Ok(_) => {
+            // Build completed response: AT+STREAM=id#END#COMPLETED
+            let completed = encode_response(b"STREAM", id, &[b"END", b"COMPLETED"]);
+            AT_CH.send(reply(id, completed)).await;
+        }
+        Err(e) => {
+            error!("Stream end rejected");
+            AT_CH.send(Msg::Err(id, e)).await;
+        }
+    }
+}
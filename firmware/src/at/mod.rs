@@ -1,9 +1,13 @@
 // SPDX-FileCopyrightText: 2025 hexaTune LLC
 // SPDX-License-Identifier: MIT
 
+mod command;
+pub use command::*;
 mod dispatcher;
 pub use dispatcher::*;
+#[cfg(target_os = "none")]
 mod at_task;
+#[cfg(target_os = "none")]
 pub use at_task::*;
 mod handlers;
 pub use handlers::*;
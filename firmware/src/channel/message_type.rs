@@ -3,11 +3,15 @@
 
 use heapless::String;
 
+use crate::at::{CalibrateSub, OperationSub, SlotSub};
+use crate::dds::{Glide, MilliHertz, Rgb, Sweep, Waveform};
 use crate::error::FirmwareError;
-use hexa_tune_proto_embedded::command::OperationSub;
 
 pub type MsgId = u32;
-pub type MsgString = String<64>;
+/// Longest AT line, sized for the worst-case `AT+OPERATION=..#GENERATING#..`
+/// status of 187 bytes: eleven fields with every counter at its maximum.
+pub const MSG_LEN: usize = 192;
+pub type MsgString = String<MSG_LEN>;
 
 pub enum Msg {
     AtRxLine(MsgString),
@@ -15,10 +19,55 @@ pub enum Msg {
     Done(MsgId),
     Err(MsgId, FirmwareError),
     UsbTxLine(MsgString),
-    RgbSet { id: u32, r: u8, g: u8, b: u8 },
-    FreqSet { id: u32, freq: u32, time_ms: u32 },
+    /// `id` is `None` for colour cues from the DDS task, which get no reply
+    RgbSet {
+        id: Option<u32>,
+        r: u8,
+        g: u8,
+        b: u8,
+    },
+    FreqSet {
+        id: u32,
+        freq: MilliHertz,
+        time_ms: u32,
+        phase: Option<u8>,
+        glide: Option<Glide>,
+        colour: Option<Rgb>,
+    },
+    SweepSet {
+        id: u32,
+        start: MilliHertz,
+        time_ms: u32,
+        sweep: Sweep,
+    },
+    PhaseSet {
+        id: u32,
+        phase: u8,
+    },
+    WaveformSet {
+        id: u32,
+        waveform: Waveform,
+    },
+    FreqInfo {
+        id: u32,
+        freq: MilliHertz,
+    },
+    Calibrate {
+        id: u32,
+        sub: CalibrateSub,
+    },
+    CalibrateQuery,
     SetDdsAvailable(bool),
     SetOperationStatus(MsgString),
     GetOperationStatus,
-    OperationCmd { id: u32, sub: OperationSub },
+    OperationCmd {
+        id: u32,
+        sub: OperationSub,
+    },
+    /// Verified bulk chunk `id` is queued for `take_bulk`
+    BulkChunk(MsgId),
+    SlotCmd {
+        id: u32,
+        sub: SlotSub,
+    },
 }
@@ -0,0 +1,272 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use core::convert::Infallible;
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::SpiBus;
+
+use crate::dds::{DdsDevice, MilliHertz, PHASE_STEPS, Waveform, calibrated_clk};
+use crate::error::FirmwareError;
+
+// Control register bits
+const CTRL_B28: u16 = 1 << 13;
+const CTRL_FSELECT: u16 = 1 << 11;
+const CTRL_PSELECT: u16 = 1 << 10;
+const CTRL_RESET: u16 = 1 << 8;
+const CTRL_SLEEP1: u16 = 1 << 7;
+const CTRL_SLEEP12: u16 = 1 << 6;
+const CTRL_OPBITEN: u16 = 1 << 5;
+const CTRL_DIV2: u16 = 1 << 3;
+const CTRL_MODE: u16 = 1 << 1;
+
+// Register address bits
+const REG_FREQ: [u16; 2] = [0x4000, 0x8000];
+const REG_PHASE: [u16; 2] = [0xC000, 0xE000];
+
+const FTW_BITS: u32 = 28;
+const FREQ_WORD_MASK: u32 = 0x3FFF;
+const PHASE_WORD_SHIFT: u32 = 7; // 32 phase steps onto the 12-bit phase register
+
+/// SPI driver for the AD9833/AD9837.
+///
+/// Each tuning word load goes to the idle FREQ/PHASE register pair and is then
+/// selected through the control register, so the output switches without a
+/// reset. Expects SPI mode 2 (clock idle high, data captured on the falling edge).
+pub struct Ad983x<S, P> {
+    spi: S,
+    fsync: P,
+    mclk_hz: u32,
+    clk_ppb: i32,
+    // MCLK with the calibration applied
+    clk: MilliHertz,
+    waveform: Waveform,
+    phase: u8,
+    active: usize,
+}
+
+impl<S, P> Ad983x<S, P>
+where
+    S: SpiBus<u8>,
+    P: OutputPin<Error = Infallible>,
+{
+    pub fn new(spi: S, fsync: P, mclk_hz: u32) -> Self {
+        Self {
+            spi,
+            fsync,
+            mclk_hz,
+            clk_ppb: 0,
+            clk: calibrated_clk(mclk_hz, 0),
+            waveform: Waveform::Sine,
+            phase: 0,
+            active: 0,
+        }
+    }
+
+    fn write_word(&mut self, word: u16) -> Option<FirmwareError> {
+        let Ok(()) = self.fsync.set_low();
+        let result = self
+            .spi
+            .write(&word.to_be_bytes())
+            .and_then(|_| self.spi.flush());
+        let Ok(()) = self.fsync.set_high();
+        result.err().map(|_| FirmwareError::DdsBus)
+    }
+
+    fn ctrl(&self, extra: u16) -> u16 {
+        let waveform = match self.waveform {
+            Waveform::Sine => 0,
+            Waveform::Triangle => CTRL_MODE,
+            Waveform::Square => CTRL_OPBITEN | CTRL_DIV2,
+        };
+        let select = if self.active == 1 {
+            CTRL_FSELECT | CTRL_PSELECT
+        } else {
+            0
+        };
+        CTRL_B28 | waveform | select | extra
+    }
+
+    fn write_freq_phase(&mut self, reg: usize, ftw: u32, phase: u8) -> Option<FirmwareError> {
+        let phase = (phase as u32) << PHASE_WORD_SHIFT;
+        let words = [
+            REG_FREQ[reg] | (ftw & FREQ_WORD_MASK) as u16,
+            REG_FREQ[reg] | ((ftw >> 14) & FREQ_WORD_MASK) as u16,
+            REG_PHASE[reg] | phase as u16,
+        ];
+        for word in words {
+            if let Some(e) = self.write_word(word) {
+                return Some(e);
+            }
+        }
+        None
+    }
+}
+
+impl<S, P> DdsDevice for Ad983x<S, P>
+where
+    S: SpiBus<u8>,
+    P: OutputPin<Error = Infallible>,
+{
+    async fn reset(&mut self) -> Option<FirmwareError> {
+        self.active = 0;
+        if let Some(e) = self.write_word(self.ctrl(CTRL_RESET)) {
+            return Some(e);
+        }
+        if let Some(e) = self.write_freq_phase(0, 0, 0) {
+            return Some(e);
+        }
+        self.write_word(self.ctrl(0))
+    }
+
+    async fn down(&mut self) -> Option<FirmwareError> {
+        self.write_word(self.ctrl(CTRL_SLEEP1 | CTRL_SLEEP12))
+    }
+
+    async fn up(&mut self) -> Option<FirmwareError> {
+        self.write_word(self.ctrl(0))
+    }
+
+    async fn load_ftw(&mut self, ftw: u32) -> Option<FirmwareError> {
+        let next = self.active ^ 1;
+        if let Some(e) = self.write_freq_phase(next, ftw, self.phase) {
+            return Some(e);
+        }
+        self.active = next;
+        self.write_word(self.ctrl(0))
+    }
+
+    fn set_phase(&mut self, phase: u8) -> Option<FirmwareError> {
+        self.phase = phase % PHASE_STEPS;
+        None
+    }
+
+    fn set_waveform(&mut self, waveform: Waveform) -> Option<FirmwareError> {
+        self.waveform = waveform;
+        None
+    }
+
+    fn set_clock_ppb(&mut self, ppb: i32) {
+        self.clk_ppb = ppb;
+        self.clk = calibrated_clk(self.mclk_hz, ppb);
+    }
+
+    fn clock_ppb(&self) -> i32 {
+        self.clk_ppb
+    }
+
+    fn freq_to_ftw(&self, freq: MilliHertz) -> u32 {
+        let num = (freq.0 as u128) << FTW_BITS;
+        let den = self.clk.0 as u128;
+        (((num + den / 2) / den) as u32).min((1 << FTW_BITS) - 1)
+    }
+
+    fn ftw_to_freq(&self, ftw: u32) -> MilliHertz {
+        let num = ftw as u128 * self.clk.0 as u128;
+        MilliHertz(((num + (1 << (FTW_BITS - 1))) >> FTW_BITS) as u64)
+    }
+
+    fn max_freq(&self) -> MilliHertz {
+        MilliHertz(self.clk.0 / 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::dds::mock::{MockBus, MockOutput};
+
+    const MCLK_HZ: u32 = 25_000_000;
+
+    #[test]
+    fn reset_clears_freq0_and_phase0_then_runs_from_them() {
+        let bus = MockBus::new();
+        let mut dds = bus.ad983x(MCLK_HZ);
+        // The phase only applies to tuning word loads
+        dds.set_phase(8);
+        assert!(block_on(dds.reset()).is_none());
+
+        // B28 with RESET, FREQ0 LSBs and MSBs, PHASE0, then B28 alone
+        assert_eq!(
+            bus.spi_words().as_slice(),
+            [0x2100, 0x4000, 0x4000, 0xC000, 0x2000]
+        );
+    }
+
+    #[test]
+    fn loads_alternate_between_the_register_pairs() {
+        let bus = MockBus::new();
+        let mut dds = bus.ad983x(MCLK_HZ);
+        block_on(dds.reset());
+        bus.clear();
+
+        let ftw = (0x1555 << 14) | 0x2AAA;
+        assert!(block_on(dds.load_ftw(ftw)).is_none());
+        assert!(dds.set_phase(8).is_none());
+        assert!(block_on(dds.load_ftw(ftw + 1)).is_none());
+
+        // The idle FREQ1/PHASE1 pair is written, then selected with FSELECT and
+        // PSELECT, and the next load goes back to FREQ0/PHASE0
+        assert_eq!(
+            bus.spi_words().as_slice(),
+            [
+                0x8000 | 0x2AAA,
+                0x8000 | 0x1555,
+                0xE000,
+                0x2C00,
+                0x4000 | 0x2AAB,
+                0x4000 | 0x1555,
+                // Phase 8 of 32 on the 12-bit register
+                0xC000 | 8 << 7,
+                0x2000,
+            ]
+        );
+        assert_eq!(
+            bus.outputs().as_slice(),
+            [
+                MockOutput::On { ftw, phase: 0 },
+                MockOutput::On {
+                    ftw: ftw + 1,
+                    phase: 8
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn waveforms_set_the_mode_bits() {
+        let bus = MockBus::new();
+        let mut dds = bus.ad983x(MCLK_HZ);
+        for waveform in [Waveform::Triangle, Waveform::Square, Waveform::Sine] {
+            assert!(dds.set_waveform(waveform).is_none());
+            block_on(dds.up());
+        }
+
+        // MODE for the triangle, OPBITEN with DIV2 for a square at the tuned frequency
+        assert_eq!(bus.spi_words().as_slice(), [0x2002, 0x2028, 0x2000]);
+    }
+
+    #[test]
+    fn down_sleeps_the_dac_and_the_clock() {
+        let bus = MockBus::new();
+        let mut dds = bus.ad983x(MCLK_HZ);
+        block_on(dds.down());
+
+        assert_eq!(bus.spi_words().as_slice(), [0x20C0]);
+        assert_eq!(bus.outputs().as_slice(), [MockOutput::Off]);
+    }
+
+    #[test]
+    fn tuning_words_are_28_bits() {
+        let bus = MockBus::new();
+        let dds = bus.ad983x(MCLK_HZ);
+
+        // round(1 kHz * 2^28 / 25 MHz)
+        assert_eq!(dds.freq_to_ftw(MilliHertz::from_hz(1000)), 10_737);
+        assert_eq!(dds.ftw_to_freq(10_737), MilliHertz(999_961));
+        assert_eq!(dds.freq_to_ftw(dds.max_freq()), 1 << 27);
+        assert_eq!(dds.freq_to_ftw(MilliHertz::from_hz(MCLK_HZ)), (1 << 28) - 1);
+    }
+}
@@ -1,143 +1,336 @@
 // SPDX-FileCopyrightText: 2025 hexaTune LLC
 // SPDX-License-Identifier: MIT
 
-use defmt::*;
-use embassy_rp::gpio::Output;
+use core::convert::Infallible;
+
 use embassy_time::{Duration, Timer};
+use embedded_hal::digital::OutputPin;
 
+use crate::dds::{DdsDevice, MilliHertz, calibrated_clk};
 use crate::error::FirmwareError;
 
-const CTRL_PWRDOWN: u8 = 1 << 7;
-const CTRL_SLEEP: u8 = 1 << 6;
-//const CTRL_X6: u8 = 1 << 5; // AD9851 x6 PLL
-const CTRL_PHASE0: u8 = 0;
+// Control byte, shifted LSB first as W32..W39
+const CTRL_X6: u8 = 1 << 0; // AD9851 x6 PLL
+const CTRL_PWRDOWN: u8 = 1 << 2;
+const CTRL_PHASE_SHIFT: u8 = 3;
+const CTRL_PHASE_MASK: u8 = 0x1F;
 const PULSE_US: u64 = 1;
 
-pub struct Ad985x {
-    wclk: Output<'static>,
-    fq_ud: Output<'static>,
-    data: Output<'static>,
-    rst: Output<'static>,
-    ref_clk_hz: u32,
-    ctrl_base: u8,
+/// AD985x family member and its reference clock setup.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // only the configured variant is constructed
+pub enum DdsVariant {
+    Ad9850,
+    /// AD9851, optionally multiplying the reference clock by 6 with its PLL
+    Ad9851 {
+        x6: bool,
+    },
+}
+
+impl DdsVariant {
+    /// System clock seen by the phase accumulator for a given reference clock.
+    pub const fn sys_clk_hz(self, ref_clk_hz: u32) -> u32 {
+        match self {
+            DdsVariant::Ad9851 { x6: true } => ref_clk_hz * 6,
+            _ => ref_clk_hz,
+        }
+    }
+
+    const fn ctrl_base(self) -> u8 {
+        match self {
+            DdsVariant::Ad9851 { x6: true } => CTRL_X6,
+            _ => 0,
+        }
+    }
+}
+
+/// Serial interface to the AD985x W_CLK, DATA, FQ_UD and RESET lines.
+#[allow(async_fn_in_trait)]
+pub trait Ad985xLink {
+    /// Shift `bits` of `value` LSB first on DATA/W_CLK, then strobe FQ_UD.
+    async fn write(&mut self, value: u64, bits: u8);
+
+    /// Pulse the RESET line.
+    async fn pulse_reset(&mut self);
+}
+
+/// Bit-banged link over four GPIOs.
+pub struct GpioLink<P> {
+    wclk: P,
+    fq_ud: P,
+    data: P,
+    rst: P,
 }
 
-impl Ad985x {
-    pub fn new(
-        wclk: Output<'static>,
-        fq_ud: Output<'static>,
-        data: Output<'static>,
-        rst: Output<'static>,
-        ref_clk_hz: u32,
-        ctrl_base: u8,
-    ) -> Self {
+impl<P> GpioLink<P>
+where
+    P: OutputPin<Error = Infallible>,
+{
+    pub fn new(wclk: P, fq_ud: P, data: P, rst: P) -> Self {
         Self {
             wclk,
             fq_ud,
             data,
             rst,
-            ref_clk_hz,
-            ctrl_base,
         }
     }
 
     #[inline(always)]
-    async fn pulse_high_low(pin: &mut Output<'static>) {
-        pin.set_high();
+    async fn pulse_high_low(pin: &mut P) {
+        let Ok(()) = pin.set_high();
         Timer::after(Duration::from_micros(PULSE_US)).await;
-        pin.set_low();
+        let Ok(()) = pin.set_low();
         Timer::after(Duration::from_micros(PULSE_US)).await;
     }
+}
 
-    async fn shift_lsb_first(&mut self, mut value: u64, bits: usize) {
+impl<P> Ad985xLink for GpioLink<P>
+where
+    P: OutputPin<Error = Infallible>,
+{
+    async fn write(&mut self, mut value: u64, bits: u8) {
         for _ in 0..bits {
-            if (value & 1) != 0 {
-                self.data.set_high();
-            } else {
-                self.data.set_low();
-            }
-            self.wclk.set_high();
-            Timer::after(Duration::from_micros(PULSE_US)).await;
-            self.wclk.set_low();
-            Timer::after(Duration::from_micros(PULSE_US)).await;
+            let Ok(()) = self.data.set_state(((value & 1) != 0).into());
+            Self::pulse_high_low(&mut self.wclk).await;
             value >>= 1;
         }
-    }
-
-    async fn write_ftw_ctrl(&mut self, ftw: u32, ctrl: u8) {
-        self.shift_lsb_first(ftw as u64, 32).await;
-        self.shift_lsb_first(ctrl as u64, 8).await;
         Self::pulse_high_low(&mut self.fq_ud).await;
     }
 
-    fn hz_to_ftw(&self, freq_hz: u32) -> u32 {
-        let num = (freq_hz as u64) << 32;
-        let den = self.ref_clk_hz as u64;
-        ((num + den / 2) / den) as u32
-    }
-
-    pub async fn reset(&mut self) -> Option<FirmwareError> {
-        self.rst.set_high();
+    async fn pulse_reset(&mut self) {
+        let Ok(()) = self.rst.set_high();
         Timer::after(Duration::from_micros(5)).await;
-        self.rst.set_low();
+        let Ok(()) = self.rst.set_low();
         Timer::after(Duration::from_micros(5)).await;
+    }
+}
 
-        for _ in 0..5 {
-            Self::pulse_high_low(&mut self.wclk).await;
+/// Longest frame the PIO load program shifts out, see `pio_fifo_words`.
+pub const PIO_MAX_BITS: u8 = 64;
+
+/// Encode a frame for the PIO serial load program: `bits - 1`, then `value`
+/// in 32-bit words, LSB first. Returns the words and how many of them to push.
+///
+/// `bits` is clamped to 1..=`PIO_MAX_BITS`.
+pub fn pio_fifo_words(value: u64, bits: u8) -> ([u32; 3], usize) {
+    let bits = bits.clamp(1, PIO_MAX_BITS);
+    let words = [(bits - 1) as u32, value as u32, (value >> 32) as u32];
+    (words, 1 + (bits as usize).div_ceil(32))
+}
+
+pub struct Ad985x<L> {
+    link: L,
+    sys_clk_hz: u32,
+    clk_ppb: i32,
+    // System clock with the calibration applied
+    clk: MilliHertz,
+    ctrl_base: u8,
+    phase: u8,
+}
+
+impl<L: Ad985xLink> Ad985x<L> {
+    pub fn new(link: L, variant: DdsVariant, ref_clk_hz: u32) -> Self {
+        let sys_clk_hz = variant.sys_clk_hz(ref_clk_hz);
+        Self {
+            link,
+            sys_clk_hz,
+            clk_ppb: 0,
+            clk: calibrated_clk(sys_clk_hz, 0),
+            ctrl_base: variant.ctrl_base(),
+            phase: 0,
         }
-        Self::pulse_high_low(&mut self.fq_ud).await;
+    }
 
-        self.write_ftw_ctrl(0, self.ctrl_base & !(CTRL_SLEEP | CTRL_PWRDOWN))
+    async fn write_ftw_ctrl(&mut self, ftw: u32, ctrl: u8) {
+        self.link
+            .write(((ctrl as u64) << 32) | ftw as u64, 40)
             .await;
+    }
 
-        None
+    fn phase_bits(&self) -> u8 {
+        (self.phase & CTRL_PHASE_MASK) << CTRL_PHASE_SHIFT
     }
+}
+
+impl<L: Ad985xLink> DdsDevice for Ad985x<L> {
+    async fn reset(&mut self) -> Option<FirmwareError> {
+        self.link.pulse_reset().await;
+
+        // W_CLK then FQ_UD switches the chip into serial load mode
+        self.link.write(0, 5).await;
+
+        self.write_ftw_ctrl(0, self.ctrl_base & !CTRL_PWRDOWN).await;
 
-    /*pub async fn sleep(&mut self) -> Option<FirmwareError> {
-        self.write_ftw_ctrl(0, self.ctrl_base | CTRL_SLEEP).await;
         None
-    }*/
+    }
 
-    pub async fn down(&mut self) -> Option<FirmwareError> {
+    async fn down(&mut self) -> Option<FirmwareError> {
         self.write_ftw_ctrl(0, self.ctrl_base | CTRL_PWRDOWN).await;
         None
     }
 
-    pub async fn up(&mut self) -> Option<FirmwareError> {
-        self.write_ftw_ctrl(0, self.ctrl_base & !(CTRL_SLEEP | CTRL_PWRDOWN))
-            .await;
+    async fn up(&mut self) -> Option<FirmwareError> {
+        self.write_ftw_ctrl(0, self.ctrl_base & !CTRL_PWRDOWN).await;
         None
     }
 
-    pub async fn set_freq(&mut self, freq_hz: u32, dwell_ms: u32) -> Option<FirmwareError> {
-        if let Some(e) = self.down().await {
-            return Some(e);
-        }
+    async fn load_ftw(&mut self, ftw: u32) -> Option<FirmwareError> {
+        let ctrl = (self.ctrl_base & !CTRL_PWRDOWN) | self.phase_bits();
+        self.write_ftw_ctrl(ftw, ctrl).await;
+        None
+    }
 
-        if let Some(e) = self.up().await {
-            return Some(e);
-        }
+    fn set_phase(&mut self, phase: u8) -> Option<FirmwareError> {
+        self.phase = phase & CTRL_PHASE_MASK;
+        None
+    }
 
-        if let Some(e) = self.reset().await {
-            return Some(e);
-        }
+    fn set_clock_ppb(&mut self, ppb: i32) {
+        self.clk_ppb = ppb;
+        self.clk = calibrated_clk(self.sys_clk_hz, ppb);
+    }
 
-        if let Some(e) = self.set_freq_immediate(freq_hz).await {
-            return Some(e);
-        }
-        info!("Waiting time ms {}", dwell_ms);
-        Timer::after(Duration::from_millis(dwell_ms as u64)).await;
-        info!("Wait complete");
-        if let Some(e) = self.down().await {
-            return Some(e);
+    fn clock_ppb(&self) -> i32 {
+        self.clk_ppb
+    }
+
+    fn freq_to_ftw(&self, freq: MilliHertz) -> u32 {
+        let num = (freq.0 as u128) << 32;
+        let den = self.clk.0 as u128;
+        ((num + den / 2) / den) as u32
+    }
+
+    fn ftw_to_freq(&self, ftw: u32) -> MilliHertz {
+        let num = ftw as u128 * self.clk.0 as u128;
+        MilliHertz(((num + (1 << 31)) >> 32) as u64)
+    }
+
+    fn max_freq(&self) -> MilliHertz {
+        MilliHertz(self.clk.0 / 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::dds::mock::MockBus;
+
+    const CLK_HZ: u32 = 125_000_000;
+
+    #[test]
+    fn down_sets_the_power_down_bit_and_up_clears_it() {
+        let bus = MockBus::new();
+        let mut dds = bus.ad985x(DdsVariant::Ad9850, CLK_HZ);
+        assert!(block_on(dds.down()).is_none());
+        assert!(block_on(dds.up()).is_none());
+
+        // Power-down is W34, bit 2 of the control byte, with a zero tuning word
+        assert_eq!(bus.words().as_slice(), [(0, 0b100), (0, 0)]);
+    }
+
+    #[test]
+    fn tuning_words_follow_the_calibrated_clock() {
+        let bus = MockBus::new();
+        let mut dds = bus.ad985x(DdsVariant::Ad9850, CLK_HZ);
+        let freq = MilliHertz::from_hz(1000);
+
+        // A clock 1000 ppm fast needs a smaller word for the same output
+        dds.set_clock_ppb(1_000_000);
+        assert_eq!(dds.clock_ppb(), 1_000_000);
+        assert_eq!(dds.freq_to_ftw(freq), 34_325);
+        assert_eq!(dds.max_freq(), MilliHertz(62_562_500_000));
+        dds.set_clock_ppb(-1_000_000);
+        assert_eq!(dds.freq_to_ftw(freq), 34_394);
+        dds.set_clock_ppb(0);
+        assert_eq!(dds.freq_to_ftw(freq), 34_360);
+    }
+
+    #[test]
+    fn ad9851_x6_keeps_w32_set_in_every_word() {
+        let bus = MockBus::new();
+        let mut dds = bus.ad985x(DdsVariant::Ad9851 { x6: true }, 30_000_000);
+        block_on(dds.reset());
+        dds.set_phase(8);
+        // round(1 kHz * 2^32 / 180 MHz)
+        let ftw = dds.freq_to_ftw(MilliHertz::from_hz(1000));
+        assert_eq!(ftw, 23_861);
+        block_on(dds.load_ftw(ftw));
+        block_on(dds.down());
+        block_on(dds.up());
+
+        assert_eq!(
+            bus.words().as_slice(),
+            [(0, 0b1), (23_861, 8 << 3 | 0b1), (0, 0b101), (0, 0b1)]
+        );
+        assert_eq!(dds.max_freq(), MilliHertz::from_hz(90_000_000));
+    }
+
+    #[test]
+    fn ad9851_without_x6_runs_on_the_reference_clock() {
+        let bus = MockBus::new();
+        let mut dds = bus.ad985x(DdsVariant::Ad9851 { x6: false }, 30_000_000);
+        block_on(dds.load_ftw(143_166));
+
+        assert_eq!(bus.words().as_slice(), [(143_166, 0)]);
+        assert_eq!(dds.freq_to_ftw(MilliHertz::from_hz(1000)), 143_166);
+        assert_eq!(dds.max_freq(), MilliHertz::from_hz(15_000_000));
+    }
+
+    #[test]
+    fn phase_goes_into_w35_to_w39_of_tuning_word_loads() {
+        let bus = MockBus::new();
+        let mut dds = bus.ad985x(DdsVariant::Ad9850, CLK_HZ);
+        assert!(dds.set_phase(8).is_none());
+        block_on(dds.load_ftw(34_360));
+        // Only five bits, 33 steps is one step
+        assert!(dds.set_phase(33).is_none());
+        block_on(dds.load_ftw(34_360));
+        block_on(dds.down());
+
+        assert_eq!(
+            bus.words().as_slice(),
+            [(34_360, 8 << 3), (34_360, 1 << 3), (0, 0b100)]
+        );
+    }
+
+    #[test]
+    fn tuning_words_roundtrip_without_overflow() {
+        let bus = MockBus::new();
+        let dds = bus.ad985x(DdsVariant::Ad9850, CLK_HZ);
+
+        // round(1 kHz * 2^32 / 125 MHz), which comes out at 1000.0076 Hz
+        assert_eq!(dds.freq_to_ftw(MilliHertz::from_hz(1000)), 34_360);
+        assert_eq!(dds.ftw_to_freq(34_360), MilliHertz(1_000_008));
+        assert_eq!(dds.freq_to_ftw(dds.max_freq()), 1 << 31);
+        // 2^32 * 125 GHz needs more than 64 bits
+        assert_eq!(dds.ftw_to_freq(u32::MAX), MilliHertz(124_999_999_971));
+
+        // A step is 29 mHz, so the millihertz a word reads back as lead to it again
+        for ftw in [0, 1, 17, 34_360, 1 << 31, u32::MAX] {
+            assert_eq!(dds.freq_to_ftw(dds.ftw_to_freq(ftw)), ftw);
         }
+    }
 
-        None
+    #[test]
+    fn pio_frame_of_5_bits_is_one_data_word() {
+        // The reset-into-serial strobe: bit count 4, then the bits
+        let (words, len) = pio_fifo_words(0, 5);
+        assert_eq!(&words[..len], [4, 0]);
     }
 
-    async fn set_freq_immediate(&mut self, freq_hz: u32) -> Option<FirmwareError> {
-        let ftw = self.hz_to_ftw(freq_hz);
-        self.write_ftw_ctrl(ftw, self.ctrl_base | CTRL_PHASE0).await;
-        None
+    #[test]
+    fn pio_frame_of_40_bits_splits_into_two_data_words() {
+        // A tuning word and its control byte as W0..W39
+        let value = (0xA5u64 << 32) | 0x1234_5678;
+        let (words, len) = pio_fifo_words(value, 40);
+        assert_eq!(&words[..len], [39, 0x1234_5678, 0xA5]);
+    }
+
+    #[test]
+    fn pio_frame_bit_count_is_clamped() {
+        assert_eq!(pio_fifo_words(1, 0), ([0, 1, 0], 2));
+        assert_eq!(pio_fifo_words(u64::MAX, 80), ([63, u32::MAX, u32::MAX], 3));
     }
 }
@@ -0,0 +1,111 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use embassy_rp::Peri;
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::pio::{
+    Common, Config, Direction, FifoJoin, Instance, LoadedProgram, PioPin, ShiftConfig,
+    ShiftDirection, StateMachine,
+};
+use embassy_time::{Duration, Timer};
+use fixed::traits::ToFixed;
+
+use crate::dds::{Ad985xLink, pio_fifo_words};
+
+/// PIO instruction clock; one W_CLK period takes three instructions
+/// (`pull ifempty`, `out`, `jmp`), so W_CLK runs at a third of it.
+const PIO_CLK_HZ: u32 = 25_000_000;
+
+/// Serial load program for the AD985x.
+///
+/// Each frame is a bit count minus one followed by the data words, LSB first.
+/// DATA is driven from OUT, W_CLK by side-set and FQ_UD by SET; a word is
+/// pushed to the RX FIFO once FQ_UD has been strobed.
+pub struct Ad985xPioProgram<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> Ad985xPioProgram<'d, PIO> {
+    pub fn new(common: &mut Common<'d, PIO>) -> Self {
+        let prg = embassy_rp::pio::program::pio_asm!(
+            r#"
+                .side_set 1
+
+                .wrap_target
+                    pull block          side 0  ; bit count - 1
+                    mov x, osr          side 0
+                    pull block          side 0  ; first data word
+                bitloop:
+                    pull ifempty block  side 0  ; next data word after 32 bits
+                    out pins, 1         side 0  ; DATA set up while W_CLK is low
+                    jmp x-- bitloop     side 1  ; W_CLK rising edge latches DATA
+                    set pins, 1         side 0  ; FQ_UD strobe
+                    set pins, 0         side 0
+                    push noblock        side 0  ; frame done
+                .wrap
+            "#
+        );
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// AD985x link driven by a PIO state machine, RESET stays a plain GPIO.
+pub struct PioLink<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    rst: Output<'d>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioLink<'d, PIO, SM> {
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        wclk: Peri<'d, impl PioPin>,
+        fq_ud: Peri<'d, impl PioPin>,
+        data: Peri<'d, impl PioPin>,
+        rst: Output<'d>,
+        program: &Ad985xPioProgram<'d, PIO>,
+    ) -> Self {
+        let wclk = common.make_pio_pin(wclk);
+        let fq_ud = common.make_pio_pin(fq_ud);
+        let data = common.make_pio_pin(data);
+        sm.set_pins(Level::Low, &[&wclk, &fq_ud, &data]);
+        sm.set_pin_dirs(Direction::Out, &[&wclk, &fq_ud, &data]);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[&wclk]);
+        cfg.set_set_pins(&[&fq_ud]);
+        cfg.set_out_pins(&[&data]);
+        cfg.shift_out = ShiftConfig {
+            auto_fill: false,
+            threshold: 32,
+            direction: ShiftDirection::Right,
+        };
+        cfg.fifo_join = FifoJoin::Duplex;
+        cfg.clock_divider = clk_sys_freq().div_ceil(PIO_CLK_HZ).max(1).to_fixed();
+        sm.set_config(&cfg);
+        sm.set_enable(true);
+
+        Self { sm, rst }
+    }
+}
+
+impl<PIO: Instance, const SM: usize> Ad985xLink for PioLink<'_, PIO, SM> {
+    async fn write(&mut self, value: u64, bits: u8) {
+        let (words, len) = pio_fifo_words(value, bits);
+        for word in &words[..len] {
+            self.sm.tx().wait_push(*word).await;
+        }
+        // Block until FQ_UD has been strobed so the load is complete on return
+        self.sm.rx().wait_pull().await;
+    }
+
+    async fn pulse_reset(&mut self) {
+        self.rst.set_high();
+        Timer::after(Duration::from_micros(5)).await;
+        self.rst.set_low();
+        Timer::after(Duration::from_micros(5)).await;
+    }
+}
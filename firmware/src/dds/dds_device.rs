@@ -0,0 +1,70 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use crate::dds::{MilliHertz, Waveform};
+use crate::error::FirmwareError;
+
+/// Hardware-agnostic interface of a DDS chip.
+///
+/// `dds_task` only talks to the synthesizer through this trait, so the same
+/// operation logic runs against the AD985x on the board or against a mock
+/// backend on the host.
+#[allow(async_fn_in_trait)]
+pub trait DdsDevice {
+    /// Reset the chip and leave it powered up with a zero tuning word.
+    async fn reset(&mut self) -> Option<FirmwareError>;
+
+    /// Power down the output stage.
+    async fn down(&mut self) -> Option<FirmwareError>;
+
+    /// Power up the output stage.
+    async fn up(&mut self) -> Option<FirmwareError>;
+
+    /// Load a frequency tuning word and latch it into the chip.
+    async fn load_ftw(&mut self, ftw: u32) -> Option<FirmwareError>;
+
+    /// Set the phase offset, in 11.25 degree steps, applied by the next tuning word load.
+    fn set_phase(&mut self, phase: u8) -> Option<FirmwareError>;
+
+    /// Select the output waveform; chips without waveform control only accept sine.
+    fn set_waveform(&mut self, waveform: Waveform) -> Option<FirmwareError> {
+        match waveform {
+            Waveform::Sine => None,
+            _ => Some(FirmwareError::WaveformUnsupported),
+        }
+    }
+
+    /// Correct the reference clock by `ppb` parts per billion; tuning words are
+    /// computed against the corrected clock from then on.
+    fn set_clock_ppb(&mut self, ppb: i32);
+
+    /// Reference clock correction currently applied, in ppb.
+    fn clock_ppb(&self) -> i32;
+
+    /// Convert a frequency to the closest tuning word.
+    fn freq_to_ftw(&self, freq: MilliHertz) -> u32;
+
+    /// Convert a tuning word back to the frequency it synthesizes.
+    fn ftw_to_freq(&self, ftw: u32) -> MilliHertz;
+
+    /// Highest frequency the chip can synthesize (Nyquist limit of its system clock).
+    fn max_freq(&self) -> MilliHertz;
+
+    /// The frequency actually emitted for a requested `freq`.
+    fn achieved_freq(&self, freq: MilliHertz) -> MilliHertz {
+        self.ftw_to_freq(self.freq_to_ftw(freq))
+    }
+
+    /// Power-cycle and reset the chip, leaving it up with a zero tuning word.
+    async fn restart(&mut self) -> Option<FirmwareError> {
+        if let Some(e) = self.down().await {
+            return Some(e);
+        }
+
+        if let Some(e) = self.up().await {
+            return Some(e);
+        }
+
+        self.reset().await
+    }
+}
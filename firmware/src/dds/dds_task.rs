@@ -1,165 +1,3219 @@
 // SPDX-FileCopyrightText: 2025 hexaTune LLC
 // SPDX-License-Identifier: MIT
 
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use defmt::*;
+use embassy_futures::select::{Either, select};
+use embassy_futures::yield_now;
+#[cfg(all(target_os = "none", not(feature = "ad983x")))]
+use embassy_rp::peripherals::PIO0;
+#[cfg(all(target_os = "none", feature = "ad983x"))]
+use embassy_rp::{gpio::Output, peripherals::SPI0, spi::Blocking, spi::Spi};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as Cs;
+use embassy_sync::channel::{Channel, Receiver, Sender};
 use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
 
-use {defmt_rtt as _, panic_probe as _};
-
-use hexa_tune_proto_embedded::command::OperationSub;
-
-use crate::at::{encode_error_response, encode_response, u32_to_ascii_buf};
+use crate::at::{
+    CalibrateSub, OperationSub, SlotSub, StepParams, StepRef, encode_error_response,
+    encode_response, i32_to_ascii_buf, millihertz_to_ascii_buf, reply, u32_to_ascii_buf,
+    u64_to_ascii_buf,
+};
 use crate::channel::*;
 use crate::dds::*;
 use crate::error::FirmwareError;
-use crate::{AT_CH, DDS_CH};
+use crate::rgb::host_rgb;
+#[cfg(target_os = "none")]
+use crate::storage::load_config;
+use crate::storage::{
+    SLOT_COUNT, delete_slot, load_operation, load_slot, store_operation, store_slot, update_config,
+};
+use crate::usb::{encode_ack, encode_nak, take_bulk};
+use crate::{AT_CH, CAP, DDS_CH, RGB_CH};
 
 static OPERATION: Mutex<Cs, RefCell<Operation>> = Mutex::new(RefCell::new(Operation::new()));
 
+/// Request from the AT side to a running GENERATE.
+#[derive(Clone, Copy)]
+pub enum GenerateCtrl {
+    /// `AT+OPERATION=id#STOP`
+    Stop { id: u32 },
+    /// `AT+OPERATION=id#PAUSE`
+    Pause { id: u32 },
+    /// `AT+OPERATION=id#RESUME`
+    Resume { id: u32 },
+}
+
+impl GenerateCtrl {
+    /// Id of the AT command that made the request.
+    pub fn id(self) -> u32 {
+        match self {
+            GenerateCtrl::Stop { id }
+            | GenerateCtrl::Pause { id }
+            | GenerateCtrl::Resume { id } => id,
+        }
+    }
+}
+
+/// Checked by every dwell of a running GENERATE, so it can be interrupted
+/// while the DDS task is busy.
+pub static GENERATE_CTRL: Signal<Cs, GenerateCtrl> = Signal::new();
+
+/// Position of a running GENERATE, `None` while nothing is playing.
+static PROGRESS: BlockingMutex<Cs, Cell<Option<Progress>>> = BlockingMutex::new(Cell::new(None));
+
+/// Snapshot of a running GENERATE, with the schedule in wall-clock time.
+#[derive(Clone, Copy)]
+struct Progress {
+    id: u32,
+    step_id: u32,
+    iteration: u32,
+    /// 0-based position of the current step
+    index: u32,
+    steps: u32,
+    start: Instant,
+    step_start: Instant,
+    step_end: Instant,
+    /// End of the last pass, or of the current one when repeating until stopped
+    end: Instant,
+    freq: MilliHertz,
+}
+
+impl Progress {
+    /// Build `AT+OPERATION=id#GENERATING#step_id#iteration#index#steps#
+    /// step_elapsed_ms#step_remaining_ms#elapsed_ms#remaining_ms#freq#COMPLETED`.
+    fn status(&self, now: Instant) -> Result<MsgString, FirmwareError> {
+        let ms = |from: Instant, to: Instant| to.saturating_duration_since(from).as_millis();
+        let mut sid_buf = [0u8; 10];
+        let sid_len = u32_to_ascii_buf(self.step_id, &mut sid_buf);
+        let mut iter_buf = [0u8; 10];
+        let iter_len = u32_to_ascii_buf(self.iteration, &mut iter_buf);
+        let mut index_buf = [0u8; 10];
+        let index_len = u32_to_ascii_buf(self.index, &mut index_buf);
+        let mut steps_buf = [0u8; 10];
+        let steps_len = u32_to_ascii_buf(self.steps, &mut steps_buf);
+        let mut step_elapsed_buf = [0u8; 20];
+        let step_elapsed_len = u64_to_ascii_buf(ms(self.step_start, now), &mut step_elapsed_buf);
+        let mut step_remaining_buf = [0u8; 20];
+        let step_remaining_len = u64_to_ascii_buf(ms(now, self.step_end), &mut step_remaining_buf);
+        let mut elapsed_buf = [0u8; 20];
+        let elapsed_len = u64_to_ascii_buf(ms(self.start, now), &mut elapsed_buf);
+        let mut remaining_buf = [0u8; 20];
+        let remaining_len = u64_to_ascii_buf(ms(now, self.end), &mut remaining_buf);
+        let mut freq_buf = [0u8; 24];
+        let freq_len = millihertz_to_ascii_buf(self.freq, &mut freq_buf);
+        encode_response(
+            b"OPERATION",
+            self.id,
+            &[
+                b"GENERATING",
+                &sid_buf[..sid_len],
+                &iter_buf[..iter_len],
+                &index_buf[..index_len],
+                &steps_buf[..steps_len],
+                &step_elapsed_buf[..step_elapsed_len],
+                &step_remaining_buf[..step_remaining_len],
+                &elapsed_buf[..elapsed_len],
+                &remaining_buf[..remaining_len],
+                &freq_buf[..freq_len],
+                b"COMPLETED",
+            ],
+        )
+    }
+}
+
+/// GENERATE id and start time of an ARM counting down, `None` otherwise.
+static ARMED: BlockingMutex<Cs, Cell<Option<(u32, Instant)>>> = BlockingMutex::new(Cell::new(None));
+
+/// Status of an armed or running GENERATE, timed at the moment of the query.
+pub fn progress_status() -> Option<MsgString> {
+    let now = Instant::now();
+    if let Some((id, start)) = ARMED.lock(|armed| armed.get()) {
+        // Build status: AT+OPERATION=id#ARMED#remaining_ms
+        let mut rem_buf = [0u8; 20];
+        let rem_len = u64_to_ascii_buf(
+            start.saturating_duration_since(now).as_millis(),
+            &mut rem_buf,
+        );
+        let armed = encode_response(b"OPERATION", id, &[b"ARMED", &rem_buf[..rem_len]]);
+        return Some(status_line(id, armed));
+    }
+    PROGRESS
+        .lock(|progress| progress.get())
+        .map(|progress| status_line(progress.id, progress.status(now)))
+}
+
+/// `line` as the operation status of command `id`, the `AT+ERROR` line if it
+/// could not be built.
+fn status_line(id: u32, line: Result<MsgString, FirmwareError>) -> MsgString {
+    line.unwrap_or_else(|e| encode_error_response(id, &e))
+}
+
+/// Steps a streaming GENERATE buffers ahead of playback.
+pub const STREAM_STEPS: usize = 128;
+/// Buffered steps at or below which a streaming GENERATE asks the host for more.
+const STREAM_LOW_WATER: usize = STREAM_STEPS / 4;
+
+/// Entry of the streaming buffer.
+pub enum StreamItem {
+    Step(FreqStep),
+    /// `AT+STREAM=id#END`, the operation finishes once the steps before it have played
+    End,
+}
+
+static STREAM: Channel<Cs, StreamItem, STREAM_STEPS> = Channel::new();
+
+/// Streaming GENERATE accepting steps, `None` while no stream is open.
+static STREAM_STATE: BlockingMutex<Cs, Cell<Option<StreamState>>> =
+    BlockingMutex::new(Cell::new(None));
+
+#[derive(Clone, Copy)]
+struct StreamState {
+    max_freq: MilliHertz,
+    /// END has been queued, nothing more is accepted
+    ended: bool,
+}
+
+/// Queue a step or the end marker for the open stream, returning the free
+/// buffer space left.
+pub fn stream_push(item: StreamItem) -> Result<usize, FirmwareError> {
+    STREAM_STATE.lock(|cell| {
+        let mut state = cell
+            .get()
+            .filter(|state| !state.ended)
+            .ok_or(FirmwareError::NotGenerating)?;
+        if let StreamItem::Step(step) = &item {
+            if step.freq > state.max_freq {
+                return Err(FirmwareError::FreqOutOfRange);
+            }
+        }
+        state.ended = matches!(item, StreamItem::End);
+        STREAM
+            .try_send(item)
+            .map_err(|_| FirmwareError::StreamFull)?;
+        cell.set(Some(state));
+        Ok(STREAM.free_capacity())
+    })
+}
+
+/// Whether a streaming GENERATE is accepting steps.
+pub fn stream_open() -> bool {
+    STREAM_STATE.lock(|state| state.get().is_some_and(|state| !state.ended))
+}
+
+/// Queue the steps of bulk chunk `id` for the open stream, all or none, and
+/// build its ACK carrying the free buffer space, or its NAK. Streams have no
+/// step limit, so this is how uploads larger than an operation are played.
+pub fn stream_bulk(id: u32) -> Result<MsgString, FirmwareError> {
+    let result = take_bulk(id)
+        .ok_or(FirmwareError::Hexa(
+            hexa_tune_proto_embedded::HexaError::InvalidParam,
+        ))
+        .and_then(|chunk| {
+            STREAM_STATE.lock(|cell| {
+                let state = cell
+                    .get()
+                    .filter(|state| !state.ended)
+                    .ok_or(FirmwareError::NotGenerating)?;
+                if chunk.steps.iter().any(|step| step.freq > state.max_freq) {
+                    return Err(FirmwareError::FreqOutOfRange);
+                }
+                if chunk.steps.len() > STREAM.free_capacity() {
+                    return Err(FirmwareError::StreamFull);
+                }
+                for step in chunk.steps {
+                    STREAM
+                        .try_send(StreamItem::Step(step))
+                        .map_err(|_| FirmwareError::StreamFull)?;
+                }
+                Ok(STREAM.free_capacity())
+            })
+        });
+    match result {
+        Ok(credit) => encode_ack(id, credit),
+        Err(e) => {
+            error!("Bulk chunk not queued for the stream");
+            encode_nak(id, &e)
+        }
+    }
+}
+
+/// Why a step ended early.
+enum Halt {
+    Failed(FirmwareError),
+    Stopped { id: u32 },
+}
+
+/// DDS backend fitted on this board.
+#[cfg(all(target_os = "none", not(feature = "ad983x")))]
+pub type BoardDds = Ad985x<PioLink<'static, PIO0, 1>>;
+#[cfg(all(target_os = "none", feature = "ad983x"))]
+pub type BoardDds = Ad983x<Spi<'static, SPI0, Blocking>, Output<'static>>;
+
+#[cfg(target_os = "none")]
 #[embassy_executor::task]
-pub async fn dds_task(mut ad985x: Ad985x) {
+pub async fn dds_task(mut dds: BoardDds) {
     info!("Starting DDS task");
+    let ports = DdsPorts::board();
+    if let Some(config) = load_config().await {
+        info!(
+            "Applying stored clock calibration: {} ppb",
+            config.clock_ppb
+        );
+        dds.set_clock_ppb(config.clock_ppb);
+
+        if config.autostart {
+            match load_operation().await {
+                Ok(stored) => {
+                    let id = stored.get_id();
+                    info!("Autostarting stored operation {}", id);
+                    let valid = stored.validate(dds.max_freq()).is_ok();
+                    *OPERATION.lock().await.borrow_mut() = stored.clone();
+                    match valid {
+                        true => {
+                            let timing =
+                                generate(&mut dds, ports, id, &stored, StepMode::Continuous, 0)
+                                    .await;
+                            keep_timing(timing).await;
+                        }
+                        false => error!("Stored operation {} fails validation", id),
+                    }
+                }
+                Err(_) => error!("Autostart enabled but no valid operation is stored"),
+            }
+        }
+    }
+    run_dds(&mut dds, ports).await;
+}
+
+/// Channels the DDS task talks through.
+#[derive(Clone, Copy)]
+pub struct DdsPorts<'a> {
+    /// Commands from the AT handlers
+    pub rx: Receiver<'a, Cs, Msg, CAP>,
+    /// Responses and operation status to the AT task
+    pub at: Sender<'a, Cs, Msg, CAP>,
+    /// Step colours to the RGB task
+    pub rgb: Sender<'a, Cs, Msg, CAP>,
+}
+
+impl DdsPorts<'static> {
+    /// The channels of the running firmware.
+    pub fn board() -> Self {
+        Self {
+            rx: DDS_CH.receiver(),
+            at: AT_CH.sender(),
+            rgb: RGB_CH.sender(),
+        }
+    }
+}
+
+/// DDS message loop, generic over the backend and handed its channels so it
+/// can be driven by `MockBus`. Returns on a message it does not handle.
+pub async fn run_dds<D: DdsDevice>(dds: &mut D, ports: DdsPorts<'_>) {
+    // Phase used by steps that do not carry their own, set with AT+PHASE
+    let mut default_phase: u8 = 0;
     loop {
-        match DDS_CH.receive().await {
+        match ports.rx.receive().await {
             Msg::OperationCmd { id, sub } => {
                 info!("Received OPERATION command in DDS task: {}", id);
 
                 match sub {
-                    OperationSub::Prepare => {
-                        info!("Preparing DDS operation");
+                    OperationSub::Prepare { repeat } => {
+                        info!("Preparing DDS operation, repeat {}", repeat);
 
                         let operation = OPERATION.lock().await;
                         *operation.borrow_mut() = Operation::new();
                         {
                             let mut guard = operation.borrow_mut();
                             guard.set_id(id);
+                            guard.set_repeat(repeat);
                         }
                         drop(operation);
 
                         info!("DDS operation prepared");
-                        let completed =
-                            encode_response(b"OPERATION", id, &[b"PREPARE", b"COMPLETED"]);
-                        AT_CH.send(Msg::AtCmdResponse(completed.clone())).await;
+                        let completed = status_line(
+                            id,
+                            encode_response(b"OPERATION", id, &[b"PREPARE", b"COMPLETED"]),
+                        );
+                        ports.at.send(Msg::AtCmdResponse(completed.clone())).await;
                         info!("Completed sent for PREPARE command");
 
-                        AT_CH.send(Msg::SetOperationStatus(completed)).await;
+                        ports.at.send(Msg::SetOperationStatus(completed)).await;
                     }
-                    OperationSub::Generate => {
-                        info!("Starting DDS operation");
-
-                        let mut result: Option<FirmwareError> = None;
-
-                        info!("Setting Device Available to false");
-                        AT_CH.send(Msg::SetDdsAvailable(false)).await;
-                        info!("Set Device Available to false");
-
-                        let gen_completed =
-                            encode_response(b"OPERATION", id, &[b"GENERATE", b"COMPLETED"]);
-                        AT_CH
-                            .send(Msg::SetOperationStatus(gen_completed.clone()))
-                            .await;
-
-                        // Clone steps out of the mutex
-                        let step_count;
-                        let mut step_ids = [0u32; 64];
-                        let mut step_freqs = [0u32; 64];
-                        let mut step_times = [0u32; 64];
-                        {
+                    OperationSub::Save => {
+                        info!("Saving DDS operation to flash");
+                        let current = OPERATION.lock().await.borrow().clone();
+                        let result = store_operation(&current)
+                            .await
+                            .map(|_| current.get_steps().len());
+                        send_stored_result(ports, id, b"SAVE", result).await;
+                    }
+                    OperationSub::Load => {
+                        info!("Loading DDS operation from flash");
+                        let result = match load_operation().await {
+                            Ok(stored) => {
+                                let steps = stored.get_steps().len();
+                                *OPERATION.lock().await.borrow_mut() = stored;
+                                Ok(steps)
+                            }
+                            Err(e) => Err(e),
+                        };
+                        send_stored_result(ports, id, b"LOAD", result).await;
+                    }
+                    OperationSub::Timing { index } => {
+                        let response = {
                             let operation = OPERATION.lock().await;
                             let guard = operation.borrow();
-                            let steps = guard.get_steps();
-                            step_count = steps.len();
-                            for (i, s) in steps.iter().enumerate() {
-                                step_ids[i] = s.id;
-                                step_freqs[i] = s.freq;
-                                step_times[i] = s.time_ms;
+                            timing_response(id, &guard, index)
+                        };
+                        ports.at.send(reply(id, response)).await;
+                    }
+                    OperationSub::Replace { .. }
+                    | OperationSub::Insert { .. }
+                    | OperationSub::Delete { .. }
+                    | OperationSub::Truncate { .. } => {
+                        info!("Editing DDS operation steps");
+                        match edit_steps(dds, id, sub).await {
+                            Ok(completed) => ports.at.send(Msg::AtCmdResponse(completed)).await,
+                            Err(e) => {
+                                error!("Step edit rejected");
+                                ports.at.send(Msg::Err(id, e)).await;
                             }
                         }
-
-                        for i in 0..step_count {
-                            let step_id = step_ids[i];
-                            let freq = step_freqs[i];
-                            let time_ms = step_times[i];
-
-                            // Build status: AT+OPERATION=id#GENERATING#step_id#COMPLETED
-                            let mut sid_buf = [0u8; 10];
-                            let sid_len = u32_to_ascii_buf(step_id, &mut sid_buf);
-                            let status = encode_response(
-                                b"OPERATION",
-                                id,
-                                &[b"GENERATING", &sid_buf[..sid_len], b"COMPLETED"],
-                            );
-                            AT_CH.send(Msg::SetOperationStatus(status)).await;
-
-                            info!("Setting FREQ to {} over {} ms", freq, time_ms);
-                            let err = ad985x.set_freq(freq, time_ms).await;
-                            info!("Frequency set complete.");
-
-                            if let Some(err) = err {
-                                error!("Error setting FREQ");
-                                result = Some(FirmwareError::Hexa(
-                                    hexa_tune_proto_embedded::HexaError::InvalidParam,
-                                ));
-                                let _ = err;
-                                break;
-                            } else {
-                                info!("FREQ set successfully");
+                    }
+                    OperationSub::Validate { operation_id } => {
+                        let current = OPERATION.lock().await.borrow().clone();
+                        let report = validate_response(id, &current, dds.max_freq(), operation_id);
+                        ports.at.send(reply(id, report)).await;
+                    }
+                    OperationSub::Info => {
+                        let current = OPERATION.lock().await.borrow().clone();
+                        ports.at.send(reply(id, info_response(id, &current))).await;
+                    }
+                    OperationSub::Steps { start, count } => {
+                        let current = OPERATION.lock().await.borrow().clone();
+                        send_steps(ports, id, &current, start, count).await;
+                    }
+                    OperationSub::Generate {
+                        mode,
+                        slot,
+                        operation_id,
+                    } => {
+                        let Some(operation) = playable(ports, id, slot).await else {
+                            continue;
+                        };
+                        if check_operation(dds, ports, id, &operation, operation_id).await {
+                            let timing =
+                                generate(dds, ports, id, &operation, mode, default_phase).await;
+                            if slot.is_none() {
+                                keep_timing(timing).await;
                             }
                         }
-
-                        info!("Setting Device Available to true");
-                        AT_CH.send(Msg::SetDdsAvailable(true)).await;
-                        info!("Set Device Available to true");
-
-                        if let Some(err) = result {
-                            error!("DDS operation failed");
-                            AT_CH.send(Msg::Err(id, err)).await;
-
-                            let error_status = encode_error_response(id, &err);
-                            AT_CH.send(Msg::SetOperationStatus(error_status)).await;
-                        } else {
-                            AT_CH.send(Msg::SetOperationStatus(gen_completed)).await;
+                    }
+                    OperationSub::Stream { mode } => {
+                        generate_stream(dds, ports, id, mode, default_phase).await;
+                    }
+                    OperationSub::Arm {
+                        delay_ms,
+                        mode,
+                        slot,
+                        operation_id,
+                    } => {
+                        let Some(operation) = playable(ports, id, slot).await else {
+                            continue;
+                        };
+                        if check_operation(dds, ports, id, &operation, operation_id).await
+                            && arm(ports, id, delay_ms).await
+                        {
+                            let timing =
+                                generate(dds, ports, id, &operation, mode, default_phase).await;
+                            if slot.is_none() {
+                                keep_timing(timing).await;
+                            }
                         }
                     }
+                    OperationSub::Stop | OperationSub::Pause | OperationSub::Resume => {
+                        // Handled through GENERATE_CTRL while generating
+                        ports
+                            .at
+                            .send(Msg::Err(id, FirmwareError::NotGenerating))
+                            .await;
+                    }
                 }
             }
-            Msg::FreqSet { id, freq, time_ms } => {
+            Msg::BulkChunk(id) => {
+                info!("Received bulk chunk in DDS task: {}", id);
+                let response = match append_bulk(dds, id).await {
+                    Ok(steps) => encode_ack(id, steps),
+                    Err(e) => {
+                        error!("Bulk chunk not appended");
+                        encode_nak(id, &e)
+                    }
+                };
+                ports.at.send(reply(id, response)).await;
+            }
+            Msg::SlotCmd { id, sub } => {
+                info!("Received SLOT command in DDS task: {}", id);
+                slot_command(ports, id, sub).await;
+            }
+            Msg::FreqSet {
+                id,
+                freq,
+                time_ms,
+                phase,
+                glide,
+                colour,
+            } => {
                 info!("Received FREQ command in DDS task: {}", id);
 
-                info!("Adding FREQ step to operation");
-                let operation = OPERATION.lock().await;
+                if freq > dds.max_freq() {
+                    error!("FREQ {} is above the DDS limit", freq);
+                    ports
+                        .at
+                        .send(Msg::Err(id, FirmwareError::FreqOutOfRange))
+                        .await;
+                    continue;
+                }
 
-                let step = FreqStep { id, freq, time_ms };
-                let add_result = {
-                    let mut guard = operation.borrow_mut();
-                    guard.add_step(step)
-                };
-                drop(operation);
+                info!("Adding FREQ step to operation");
+                let add_result = push_step(FreqStep {
+                    id,
+                    freq,
+                    time_ms,
+                    phase,
+                    sweep: None,
+                    glide,
+                    colour,
+                })
+                .await;
 
                 if let Err(e) = add_result {
                     error!("Failed to add step: operation is full");
-                    AT_CH.send(Msg::Err(id, e)).await;
+                    ports.at.send(Msg::Err(id, e)).await;
                 } else {
                     info!("FREQ step added to operation");
 
-                    // Build completed response: AT+FREQ=id#freq#time_ms#COMPLETED
-                    let mut freq_buf = [0u8; 10];
-                    let freq_len = u32_to_ascii_buf(freq, &mut freq_buf);
+                    // Build completed response: AT+FREQ=id#freq#time_ms#achieved#ppm#COMPLETED
+                    let achieved = dds.achieved_freq(freq);
+                    let mut freq_buf = [0u8; 24];
+                    let freq_len = millihertz_to_ascii_buf(freq, &mut freq_buf);
                     let mut time_buf = [0u8; 10];
                     let time_len = u32_to_ascii_buf(time_ms, &mut time_buf);
+                    let mut achieved_buf = [0u8; 24];
+                    let achieved_len = millihertz_to_ascii_buf(achieved, &mut achieved_buf);
+                    let mut ppm_buf = [0u8; 11];
+                    let ppm_len = i32_to_ascii_buf(achieved.ppm_from(freq), &mut ppm_buf);
                     let completed = encode_response(
                         b"FREQ",
                         id,
-                        &[&freq_buf[..freq_len], &time_buf[..time_len], b"COMPLETED"],
+                        &[
+                            &freq_buf[..freq_len],
+                            &time_buf[..time_len],
+                            &achieved_buf[..achieved_len],
+                            &ppm_buf[..ppm_len],
+                            b"COMPLETED",
+                        ],
                     );
-                    AT_CH.send(Msg::AtCmdResponse(completed)).await;
+                    ports.at.send(reply(id, completed)).await;
                     info!("Completed sent for FREQ command");
                 }
             }
+            Msg::SweepSet {
+                id,
+                start,
+                time_ms,
+                sweep,
+            } => {
+                info!("Received SWEEP command in DDS task: {}", id);
+
+                if start > dds.max_freq() || sweep.end > dds.max_freq() {
+                    error!("SWEEP {} -> {} is above the DDS limit", start, sweep.end);
+                    ports
+                        .at
+                        .send(Msg::Err(id, FirmwareError::FreqOutOfRange))
+                        .await;
+                    continue;
+                }
+
+                info!("Adding SWEEP step to operation");
+                let add_result = push_step(FreqStep {
+                    id,
+                    freq: start,
+                    time_ms,
+                    phase: None,
+                    sweep: Some(sweep),
+                    glide: None,
+                    colour: None,
+                })
+                .await;
+
+                if let Err(e) = add_result {
+                    error!("Failed to add step: operation is full");
+                    ports.at.send(Msg::Err(id, e)).await;
+                } else {
+                    // Build completed response: AT+SWEEP=id#start#end#time_ms#COMPLETED
+                    let mut start_buf = [0u8; 24];
+                    let start_len = millihertz_to_ascii_buf(start, &mut start_buf);
+                    let mut end_buf = [0u8; 24];
+                    let end_len = millihertz_to_ascii_buf(sweep.end, &mut end_buf);
+                    let mut time_buf = [0u8; 10];
+                    let time_len = u32_to_ascii_buf(time_ms, &mut time_buf);
+                    let completed = encode_response(
+                        b"SWEEP",
+                        id,
+                        &[
+                            &start_buf[..start_len],
+                            &end_buf[..end_len],
+                            &time_buf[..time_len],
+                            b"COMPLETED",
+                        ],
+                    );
+                    ports.at.send(reply(id, completed)).await;
+                    info!("Completed sent for SWEEP command");
+                }
+            }
+            Msg::PhaseSet { id, phase } => {
+                info!("Received PHASE command in DDS task: {}", id);
+
+                default_phase = phase;
+                if let Some(e) = dds.set_phase(phase) {
+                    error!("Failed to set phase");
+                    ports.at.send(Msg::Err(id, e)).await;
+                } else {
+                    // Build completed response: AT+PHASE=id#phase#COMPLETED
+                    let mut phase_buf = [0u8; 10];
+                    let phase_len = u32_to_ascii_buf(phase as u32, &mut phase_buf);
+                    let completed =
+                        encode_response(b"PHASE", id, &[&phase_buf[..phase_len], b"COMPLETED"]);
+                    ports.at.send(reply(id, completed)).await;
+                    info!("Completed sent for PHASE command");
+                }
+            }
+            Msg::WaveformSet { id, waveform } => {
+                info!("Received WAVEFORM command in DDS task: {}", id);
+
+                if let Some(e) = dds.set_waveform(waveform) {
+                    error!("Waveform {} not supported", waveform);
+                    ports.at.send(Msg::Err(id, e)).await;
+                } else {
+                    // Build completed response: AT+WAVEFORM=id#waveform#COMPLETED
+                    let completed =
+                        encode_response(b"WAVEFORM", id, &[waveform.as_bytes(), b"COMPLETED"]);
+                    ports.at.send(reply(id, completed)).await;
+                    info!("Completed sent for WAVEFORM command");
+                }
+            }
+            Msg::FreqInfo { id, freq } => {
+                info!("Received FREQINFO query in DDS task: {}", id);
+
+                if freq > dds.max_freq() {
+                    error!("FREQINFO {} is above the DDS limit", freq);
+                    ports
+                        .at
+                        .send(Msg::Err(id, FirmwareError::FreqOutOfRange))
+                        .await;
+                    continue;
+                }
+
+                // Build response: AT+FREQINFO=id#freq#achieved#ppm#ftw
+                let ftw = dds.freq_to_ftw(freq);
+                let achieved = dds.ftw_to_freq(ftw);
+                let mut freq_buf = [0u8; 24];
+                let freq_len = millihertz_to_ascii_buf(freq, &mut freq_buf);
+                let mut achieved_buf = [0u8; 24];
+                let achieved_len = millihertz_to_ascii_buf(achieved, &mut achieved_buf);
+                let mut ppm_buf = [0u8; 11];
+                let ppm_len = i32_to_ascii_buf(achieved.ppm_from(freq), &mut ppm_buf);
+                let mut ftw_buf = [0u8; 10];
+                let ftw_len = u32_to_ascii_buf(ftw, &mut ftw_buf);
+                let response = encode_response(
+                    b"FREQINFO",
+                    id,
+                    &[
+                        &freq_buf[..freq_len],
+                        &achieved_buf[..achieved_len],
+                        &ppm_buf[..ppm_len],
+                        &ftw_buf[..ftw_len],
+                    ],
+                );
+                ports.at.send(reply(id, response)).await;
+            }
+            Msg::Calibrate { id, sub } => {
+                info!("Received CALIBRATE command in DDS task: {}", id);
+
+                let ppb = match sub {
+                    CalibrateSub::Offset { ppb } => Ok(ppb),
+                    CalibrateSub::Measured {
+                        requested,
+                        measured,
+                    } => measured_ppb(dds, requested, measured),
+                };
+                let ppb = match ppb {
+                    Ok(ppb) => ppb,
+                    Err(e) => {
+                        error!("Calibration rejected");
+                        ports.at.send(Msg::Err(id, e)).await;
+                        continue;
+                    }
+                };
+
+                dds.set_clock_ppb(ppb);
+                info!("Clock calibration set to {} ppb", ppb);
+                if let Err(e) = update_config(|config| config.clock_ppb = ppb).await {
+                    error!("Failed to store calibration");
+                    ports.at.send(Msg::Err(id, e)).await;
+                    continue;
+                }
+
+                // Build completed response: AT+CALIBRATE=id#ppb#COMPLETED
+                let mut ppb_buf = [0u8; 11];
+                let ppb_len = i32_to_ascii_buf(ppb, &mut ppb_buf);
+                let completed =
+                    encode_response(b"CALIBRATE", id, &[&ppb_buf[..ppb_len], b"COMPLETED"]);
+                ports.at.send(reply(id, completed)).await;
+                info!("Completed sent for CALIBRATE command");
+            }
+            Msg::CalibrateQuery => {
+                // Build response: AT+CALIBRATE=0#ppb
+                let mut ppb_buf = [0u8; 11];
+                let ppb_len = i32_to_ascii_buf(dds.clock_ppb(), &mut ppb_buf);
+                let response = encode_response(b"CALIBRATE", 0, &[&ppb_buf[..ppb_len]]);
+                ports.at.send(reply(0, response)).await;
+            }
 
             _ => break,
         }
     }
 }
+
+async fn push_step(step: FreqStep) -> Result<(), FirmwareError> {
+    let operation = OPERATION.lock().await;
+    let mut guard = operation.borrow_mut();
+    guard.add_step(step)
+}
+
+/// Append bulk chunk `id` to the prepared operation, all steps or none,
+/// returning the new step count.
+async fn append_bulk<D: DdsDevice>(dds: &D, id: u32) -> Result<usize, FirmwareError> {
+    let chunk = take_bulk(id).ok_or(FirmwareError::Hexa(
+        hexa_tune_proto_embedded::HexaError::InvalidParam,
+    ))?;
+    if chunk.steps.iter().any(|step| step.freq > dds.max_freq()) {
+        return Err(FirmwareError::FreqOutOfRange);
+    }
+
+    let operation = OPERATION.lock().await;
+    let mut guard = operation.borrow_mut();
+    if guard.get_steps().len() + chunk.steps.len() > OPERATION_STEPS {
+        return Err(FirmwareError::OperationStepsFull);
+    }
+    for step in chunk.steps {
+        guard.add_step(step)?;
+    }
+    Ok(guard.get_steps().len())
+}
+
+/// Correction that makes the output for `requested` come out at `measured`.
+fn measured_ppb<D: DdsDevice>(
+    dds: &D,
+    requested: MilliHertz,
+    measured: MilliHertz,
+) -> Result<i32, FirmwareError> {
+    if requested > dds.max_freq() {
+        return Err(FirmwareError::FreqOutOfRange);
+    }
+    let achieved = dds.achieved_freq(requested);
+    if achieved.0 == 0 || measured.0 == 0 {
+        return Err(FirmwareError::Hexa(
+            hexa_tune_proto_embedded::HexaError::InvalidParam,
+        ));
+    }
+    let ppb = calibration_ppb(dds.clock_ppb(), achieved, measured);
+    if ppb.unsigned_abs() > CAL_PPB_LIMIT as u64 {
+        return Err(FirmwareError::Hexa(
+            hexa_tune_proto_embedded::HexaError::InvalidParam,
+        ));
+    }
+    Ok(ppb as i32)
+}
+
+/// Report a SAVE or LOAD: AT+OPERATION=id#SUB#steps#COMPLETED, which also
+/// becomes the operation status.
+async fn send_stored_result(
+    ports: DdsPorts<'_>,
+    id: u32,
+    sub: &[u8],
+    result: Result<usize, FirmwareError>,
+) {
+    match result {
+        Ok(steps) => {
+            let mut steps_buf = [0u8; 10];
+            let steps_len = u32_to_ascii_buf(steps as u32, &mut steps_buf);
+            let completed = status_line(
+                id,
+                encode_response(
+                    b"OPERATION",
+                    id,
+                    &[sub, &steps_buf[..steps_len], b"COMPLETED"],
+                ),
+            );
+            ports.at.send(Msg::AtCmdResponse(completed.clone())).await;
+            ports.at.send(Msg::SetOperationStatus(completed)).await;
+        }
+        Err(e) => {
+            error!("Operation flash access failed");
+            ports.at.send(Msg::Err(id, e)).await;
+        }
+    }
+}
+
+/// Run an `AT+SLOT` sub-command against the prepared operation.
+async fn slot_command(ports: DdsPorts<'_>, id: u32, sub: SlotSub) {
+    let mut slot_buf = [0u8; 10];
+    let mut steps_buf = [0u8; 10];
+    let result = match sub {
+        SlotSub::List => {
+            let mut used = 0;
+            for slot in 0..SLOT_COUNT {
+                let Ok((name, stored)) = load_slot(slot).await else {
+                    continue;
+                };
+                used += 1;
+                // Build response: AT+SLOT=id#n#name#steps#duration_ms
+                let slot_len = u32_to_ascii_buf(slot as u32, &mut slot_buf);
+                let steps_len = u32_to_ascii_buf(stored.get_steps().len() as u32, &mut steps_buf);
+                let mut duration_buf = [0u8; 20];
+                let duration_len = u64_to_ascii_buf(stored.duration_ms(), &mut duration_buf);
+                let line = encode_response(
+                    b"SLOT",
+                    id,
+                    &[
+                        &slot_buf[..slot_len],
+                        name.as_bytes(),
+                        &steps_buf[..steps_len],
+                        &duration_buf[..duration_len],
+                    ],
+                );
+                ports.at.send(reply(id, line)).await;
+            }
+            // Build completed response: AT+SLOT=id#LIST#used#COMPLETED
+            let used_len = u32_to_ascii_buf(used, &mut steps_buf);
+            encode_response(
+                b"SLOT",
+                id,
+                &[b"LIST", &steps_buf[..used_len], b"COMPLETED"],
+            )
+        }
+        SlotSub::Store { slot, name } => {
+            info!("Storing DDS operation in slot {}", slot);
+            let current = OPERATION.lock().await.borrow().clone();
+            store_slot(slot, &name, &current).await.and_then(|_| {
+                // Build completed response: AT+SLOT=id#STORE#n#steps#COMPLETED
+                let slot_len = u32_to_ascii_buf(slot as u32, &mut slot_buf);
+                let steps_len = u32_to_ascii_buf(current.get_steps().len() as u32, &mut steps_buf);
+                encode_response(
+                    b"SLOT",
+                    id,
+                    &[
+                        b"STORE",
+                        &slot_buf[..slot_len],
+                        &steps_buf[..steps_len],
+                        b"COMPLETED",
+                    ],
+                )
+            })
+        }
+        SlotSub::Load { slot } => {
+            info!("Loading DDS operation from slot {}", slot);
+            match load_slot(slot).await {
+                Ok((_, stored)) => {
+                    // Build completed response: AT+SLOT=id#LOAD#n#steps#COMPLETED
+                    let slot_len = u32_to_ascii_buf(slot as u32, &mut slot_buf);
+                    let steps_len =
+                        u32_to_ascii_buf(stored.get_steps().len() as u32, &mut steps_buf);
+                    *OPERATION.lock().await.borrow_mut() = stored;
+                    encode_response(
+                        b"SLOT",
+                        id,
+                        &[
+                            b"LOAD",
+                            &slot_buf[..slot_len],
+                            &steps_buf[..steps_len],
+                            b"COMPLETED",
+                        ],
+                    )
+                }
+                Err(e) => Err(e),
+            }
+        }
+        SlotSub::Delete { slot } => {
+            info!("Deleting slot {}", slot);
+            delete_slot(slot).await.and_then(|_| {
+                // Build completed response: AT+SLOT=id#DELETE#n#COMPLETED
+                let slot_len = u32_to_ascii_buf(slot as u32, &mut slot_buf);
+                encode_response(
+                    b"SLOT",
+                    id,
+                    &[b"DELETE", &slot_buf[..slot_len], b"COMPLETED"],
+                )
+            })
+        }
+    };
+    match result {
+        Ok(completed) => ports.at.send(Msg::AtCmdResponse(completed)).await,
+        Err(e) => {
+            error!("Slot flash access failed");
+            ports.at.send(Msg::Err(id, e)).await;
+        }
+    }
+}
+
+/// Apply a step editing sub-command to the prepared operation and build
+/// `AT+OPERATION=id#SUB#index#steps#COMPLETED`, or `AT+OPERATION=id#TRUNCATE#steps#COMPLETED`.
+async fn edit_steps<D: DdsDevice>(
+    dds: &D,
+    id: u32,
+    sub: OperationSub,
+) -> Result<MsgString, FirmwareError> {
+    let operation = OPERATION.lock().await;
+    let mut guard = operation.borrow_mut();
+    let index_of = |target: StepRef| match target {
+        StepRef::Id(step_id) => guard.find_step(step_id).ok_or(FirmwareError::StepNotFound),
+        StepRef::Index(index) => Ok(index as usize),
+    };
+    let checked = |step: StepParams, step_id: u32| {
+        if step.freq > dds.max_freq() {
+            return Err(FirmwareError::FreqOutOfRange);
+        }
+        Ok(FreqStep {
+            id: step_id,
+            freq: step.freq,
+            time_ms: step.time_ms,
+            phase: step.phase,
+            sweep: None,
+            glide: step.glide,
+            colour: step.colour,
+        })
+    };
+
+    let (name, index): (&[u8], Option<usize>) = match sub {
+        OperationSub::Replace { target, step } => {
+            let index = index_of(target)?;
+            let step_id = guard
+                .get_steps()
+                .get(index)
+                .ok_or(FirmwareError::StepNotFound)?
+                .id;
+            guard.replace_step(index, checked(step, step_id)?)?;
+            (b"REPLACE", Some(index))
+        }
+        OperationSub::Insert { target, step } => {
+            let index = index_of(target)?;
+            guard.insert_step(index, checked(step, id)?)?;
+            (b"INSERT", Some(index))
+        }
+        OperationSub::Delete { target } => {
+            let index = index_of(target)?;
+            guard.remove_step(index)?;
+            (b"DELETE", Some(index))
+        }
+        OperationSub::Truncate { len } => {
+            guard.truncate(len as usize);
+            (b"TRUNCATE", None)
+        }
+        _ => {
+            return Err(FirmwareError::Hexa(
+                hexa_tune_proto_embedded::HexaError::InvalidParam,
+            ));
+        }
+    };
+
+    let mut index_buf = [0u8; 10];
+    let mut steps_buf = [0u8; 10];
+    let steps_len = u32_to_ascii_buf(guard.get_steps().len() as u32, &mut steps_buf);
+    match index {
+        Some(index) => {
+            let index_len = u32_to_ascii_buf(index as u32, &mut index_buf);
+            encode_response(
+                b"OPERATION",
+                id,
+                &[
+                    name,
+                    &index_buf[..index_len],
+                    &steps_buf[..steps_len],
+                    b"COMPLETED",
+                ],
+            )
+        }
+        None => encode_response(
+            b"OPERATION",
+            id,
+            &[name, &steps_buf[..steps_len], b"COMPLETED"],
+        ),
+    }
+}
+
+/// Build `AT+OPERATION=id#INFO#op_id#steps#repeat#duration_ms`.
+fn info_response(id: u32, operation: &Operation) -> Result<MsgString, FirmwareError> {
+    let mut op_id_buf = [0u8; 10];
+    let op_id_len = u32_to_ascii_buf(operation.get_id(), &mut op_id_buf);
+    let mut steps_buf = [0u8; 10];
+    let steps_len = u32_to_ascii_buf(operation.get_steps().len() as u32, &mut steps_buf);
+    let mut repeat_buf = [0u8; 10];
+    let repeat_len = u32_to_ascii_buf(operation.get_repeat(), &mut repeat_buf);
+    let mut duration_buf = [0u8; 20];
+    let duration_len = u64_to_ascii_buf(operation.duration_ms(), &mut duration_buf);
+    encode_response(
+        b"OPERATION",
+        id,
+        &[
+            b"INFO",
+            &op_id_buf[..op_id_len],
+            &steps_buf[..steps_len],
+            &repeat_buf[..repeat_len],
+            &duration_buf[..duration_len],
+        ],
+    )
+}
+
+/// Build the VALIDATE report
+/// `AT+OPERATION=id#VALIDATE#op_id#steps#repeat#pass_ms#session_ms#COMPLETED`,
+/// with `INFINITE` as the session length of a looping operation, or
+/// `AT+OPERATION=id#VALIDATE#FAILED#code[#index]` naming the offending step.
+fn validate_response(
+    id: u32,
+    operation: &Operation,
+    max_freq: MilliHertz,
+    operation_id: Option<u32>,
+) -> Result<MsgString, FirmwareError> {
+    if let Err((e, index)) = validate_operation(operation, max_freq, operation_id) {
+        let mut code_buf = [0u8; 10];
+        let code_len = u32_to_ascii_buf(e.error_code() as u32, &mut code_buf);
+        let mut index_buf = [0u8; 10];
+        return match index {
+            Some(index) => {
+                let index_len = u32_to_ascii_buf(index as u32, &mut index_buf);
+                encode_response(
+                    b"OPERATION",
+                    id,
+                    &[
+                        b"VALIDATE",
+                        b"FAILED",
+                        &code_buf[..code_len],
+                        &index_buf[..index_len],
+                    ],
+                )
+            }
+            None => encode_response(
+                b"OPERATION",
+                id,
+                &[b"VALIDATE", b"FAILED", &code_buf[..code_len]],
+            ),
+        };
+    }
+
+    let mut op_id_buf = [0u8; 10];
+    let op_id_len = u32_to_ascii_buf(operation.get_id(), &mut op_id_buf);
+    let mut steps_buf = [0u8; 10];
+    let steps_len = u32_to_ascii_buf(operation.get_steps().len() as u32, &mut steps_buf);
+    let mut repeat_buf = [0u8; 10];
+    let repeat_len = u32_to_ascii_buf(operation.get_repeat(), &mut repeat_buf);
+    let mut pass_buf = [0u8; 20];
+    let pass_len = u64_to_ascii_buf(operation.duration_ms(), &mut pass_buf);
+    let mut session_buf = [0u8; 20];
+    let session: &[u8] = match operation.session_ms() {
+        Some(ms) => {
+            let len = u64_to_ascii_buf(ms, &mut session_buf);
+            &session_buf[..len]
+        }
+        None => b"INFINITE",
+    };
+    encode_response(
+        b"OPERATION",
+        id,
+        &[
+            b"VALIDATE",
+            &op_id_buf[..op_id_len],
+            &steps_buf[..steps_len],
+            &repeat_buf[..repeat_len],
+            &pass_buf[..pass_len],
+            session,
+            b"COMPLETED",
+        ],
+    )
+}
+
+/// Validate `operation` as it would be played, and check it is the one with
+/// id `operation_id` when the host names one.
+fn validate_operation(
+    operation: &Operation,
+    max_freq: MilliHertz,
+    operation_id: Option<u32>,
+) -> Result<(), (FirmwareError, Option<usize>)> {
+    match operation_id {
+        Some(op_id) if op_id != operation.get_id() => Err((FirmwareError::OperationMismatch, None)),
+        _ => operation.validate(max_freq),
+    }
+}
+
+/// Validate `operation` ahead of GENERATE or ARM, reporting `AT+ERROR` when
+/// it cannot be played.
+async fn check_operation<D: DdsDevice>(
+    dds: &D,
+    ports: DdsPorts<'_>,
+    id: u32,
+    operation: &Operation,
+    operation_id: Option<u32>,
+) -> bool {
+    match validate_operation(operation, dds.max_freq(), operation_id) {
+        Ok(()) => true,
+        Err((e, _)) => {
+            error!("Operation fails validation");
+            ports.at.send(Msg::Err(id, e)).await;
+            false
+        }
+    }
+}
+
+/// Send `AT+OPERATION=id#STEP#index#step_id#freq#time_ms` for up to `count`
+/// steps from `start`, then `AT+OPERATION=id#STEPS#start#sent#total#COMPLETED`.
+async fn send_steps(ports: DdsPorts<'_>, id: u32, operation: &Operation, start: u32, count: u32) {
+    let steps = operation.get_steps();
+    let mut index_buf = [0u8; 10];
+    let mut sent = 0;
+    for (index, step) in steps
+        .iter()
+        .enumerate()
+        .skip(start as usize)
+        .take(count as usize)
+    {
+        let index_len = u32_to_ascii_buf(index as u32, &mut index_buf);
+        let mut step_id_buf = [0u8; 10];
+        let step_id_len = u32_to_ascii_buf(step.id, &mut step_id_buf);
+        let mut freq_buf = [0u8; 24];
+        let freq_len = millihertz_to_ascii_buf(step.freq, &mut freq_buf);
+        let mut time_buf = [0u8; 10];
+        let time_len = u32_to_ascii_buf(step.time_ms, &mut time_buf);
+        let line = encode_response(
+            b"OPERATION",
+            id,
+            &[
+                b"STEP",
+                &index_buf[..index_len],
+                &step_id_buf[..step_id_len],
+                &freq_buf[..freq_len],
+                &time_buf[..time_len],
+            ],
+        );
+        ports.at.send(reply(id, line)).await;
+        sent += 1;
+    }
+
+    let start_len = u32_to_ascii_buf(start, &mut index_buf);
+    let mut sent_buf = [0u8; 10];
+    let sent_len = u32_to_ascii_buf(sent, &mut sent_buf);
+    let mut total_buf = [0u8; 10];
+    let total_len = u32_to_ascii_buf(steps.len() as u32, &mut total_buf);
+    let completed = encode_response(
+        b"OPERATION",
+        id,
+        &[
+            b"STEPS",
+            &index_buf[..start_len],
+            &sent_buf[..sent_len],
+            &total_buf[..total_len],
+            b"COMPLETED",
+        ],
+    );
+    ports.at.send(reply(id, completed)).await;
+}
+
+/// How far past `scheduled` the current instant is, in us.
+fn lateness_us(scheduled: Instant) -> i32 {
+    let now = Instant::now().as_micros() as i64;
+    (now - scheduled.as_micros() as i64).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// Build `AT+OPERATION=id#TIMING#steps#max_us#total_us` or, for one step,
+/// `AT+OPERATION=id#TIMING#index#step_id#error_us`.
+fn timing_response(
+    id: u32,
+    operation: &Operation,
+    index: Option<u32>,
+) -> Result<MsgString, FirmwareError> {
+    let timing = operation.get_timing();
+    let Some(index) = index else {
+        let max = timing.iter().copied().max().unwrap_or(0);
+        let total = timing.iter().map(|&t| t as i64).sum::<i64>();
+        let total = total.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        let mut count_buf = [0u8; 10];
+        let count_len = u32_to_ascii_buf(timing.len() as u32, &mut count_buf);
+        let mut max_buf = [0u8; 11];
+        let max_len = i32_to_ascii_buf(max, &mut max_buf);
+        let mut total_buf = [0u8; 11];
+        let total_len = i32_to_ascii_buf(total, &mut total_buf);
+        return encode_response(
+            b"OPERATION",
+            id,
+            &[
+                b"TIMING",
+                &count_buf[..count_len],
+                &max_buf[..max_len],
+                &total_buf[..total_len],
+            ],
+        );
+    };
+
+    let i = index as usize;
+    let (Some(&error_us), Some(step)) = (timing.get(i), operation.get_steps().get(i)) else {
+        return Err(FirmwareError::Hexa(
+            hexa_tune_proto_embedded::HexaError::InvalidParam,
+        ));
+    };
+    let mut index_buf = [0u8; 10];
+    let index_len = u32_to_ascii_buf(index, &mut index_buf);
+    let mut sid_buf = [0u8; 10];
+    let sid_len = u32_to_ascii_buf(step.id, &mut sid_buf);
+    let mut error_buf = [0u8; 11];
+    let error_len = i32_to_ascii_buf(error_us, &mut error_buf);
+    encode_response(
+        b"OPERATION",
+        id,
+        &[
+            b"TIMING",
+            &index_buf[..index_len],
+            &sid_buf[..sid_len],
+            &error_buf[..error_len],
+        ],
+    )
+}
+
+/// The operation GENERATE or ARM plays: slot `slot` when given, which leaves
+/// the prepared operation as it is, otherwise the prepared one.
+async fn playable(ports: DdsPorts<'_>, id: u32, slot: Option<u8>) -> Option<Operation> {
+    let Some(slot) = slot else {
+        return Some(OPERATION.lock().await.borrow().clone());
+    };
+    info!("Loading slot {} for GENERATE", slot);
+    match load_slot(slot).await {
+        Ok((_, stored)) => Some(stored),
+        Err(e) => {
+            error!("Slot {} cannot be generated", slot);
+            ports.at.send(Msg::Err(id, e)).await;
+            None
+        }
+    }
+}
+
+/// Keep the start errors of a GENERATE of the prepared operation for TIMING.
+async fn keep_timing(timing_us: Vec<i32, OPERATION_STEPS>) {
+    let operation = OPERATION.lock().await;
+    operation.borrow_mut().set_timing(timing_us);
+}
+
+/// Count down `delay_ms` with the DDS held busy; `false` if STOP cancelled the start.
+async fn arm(ports: DdsPorts<'_>, id: u32, delay_ms: u32) -> bool {
+    GENERATE_CTRL.reset();
+    ports.at.send(Msg::SetDdsAvailable(false)).await;
+
+    let start = Instant::now() + Duration::from_millis(delay_ms as u64);
+    ARMED.lock(|armed| armed.set(Some((id, start))));
+    info!("DDS operation armed, starting in {} ms", delay_ms);
+
+    // Build completed response: AT+OPERATION=id#ARM#delay_ms#COMPLETED
+    let mut delay_buf = [0u8; 10];
+    let delay_len = u32_to_ascii_buf(delay_ms, &mut delay_buf);
+    let completed = encode_response(
+        b"OPERATION",
+        id,
+        &[b"ARM", &delay_buf[..delay_len], b"COMPLETED"],
+    );
+    ports.at.send(reply(id, completed)).await;
+
+    let cancel_id = loop {
+        match select(Timer::at(start), GENERATE_CTRL.wait()).await {
+            Either::First(()) => break None,
+            Either::Second(GenerateCtrl::Stop { id }) => break Some(id),
+            Either::Second(ctrl) => {
+                ports
+                    .at
+                    .send(Msg::Err(ctrl.id(), FirmwareError::NotGenerating))
+                    .await;
+            }
+        }
+    };
+    ARMED.lock(|armed| armed.set(None));
+
+    let Some(cancel_id) = cancel_id else {
+        return true;
+    };
+    info!("Armed DDS operation cancelled");
+    ports.at.send(Msg::SetDdsAvailable(true)).await;
+
+    // Build response: AT+OPERATION=cancel_id#STOP#ARMED#COMPLETED
+    let completed = encode_response(b"OPERATION", cancel_id, &[b"STOP", b"ARMED", b"COMPLETED"]);
+    ports.at.send(reply(cancel_id, completed)).await;
+    let status = encode_response(b"OPERATION", id, &[b"DISARMED"]);
+    ports
+        .at
+        .send(Msg::SetOperationStatus(status_line(id, status)))
+        .await;
+    false
+}
+
+/// Play `operation` until it completes, fails or is stopped, returning how
+/// late each step of the last pass started.
+async fn generate<D: DdsDevice>(
+    dds: &mut D,
+    ports: DdsPorts<'_>,
+    id: u32,
+    operation: &Operation,
+    mode: StepMode,
+    default_phase: u8,
+) -> Vec<i32, OPERATION_STEPS> {
+    info!("Starting DDS operation in {} mode", mode);
+
+    // Drop control requests that arrived while nothing was generating
+    GENERATE_CTRL.reset();
+
+    info!("Setting Device Available to false");
+    ports.at.send(Msg::SetDdsAvailable(false)).await;
+    info!("Set Device Available to false");
+
+    let gen_completed = status_line(
+        id,
+        encode_response(b"OPERATION", id, &[b"GENERATE", b"COMPLETED"]),
+    );
+    ports
+        .at
+        .send(Msg::SetOperationStatus(gen_completed.clone()))
+        .await;
+
+    let steps = operation.get_steps();
+    let repeat = operation.get_repeat();
+
+    let mut result: Result<(), Halt> = Ok(());
+
+    // Continuous mode powers the chip up once and then only swaps tuning words
+    if mode == StepMode::Continuous {
+        if let Some(e) = dds.reset().await {
+            error!("Error resetting DDS");
+            result = Err(Halt::Failed(e));
+        }
+    }
+
+    // Step boundaries are absolute deadlines from here, so per-step
+    // overhead does not add up over the session
+    let mut session = Session::new(ports, id, steps.len() as u32);
+    let mut step_start = session.start;
+    let mut timing_us: Vec<i32, OPERATION_STEPS> = Vec::new();
+    // Frequency the output was left at, where the next glide starts
+    let mut loaded: Option<MilliHertz> = None;
+    let pass = Duration::from_millis(steps.iter().map(|step| step.time_ms as u64).sum());
+
+    while result.is_ok() && !steps.is_empty() && (repeat == 0 || session.iteration < repeat) {
+        session.iteration += 1;
+        info!("Starting iteration {} of {}", session.iteration, repeat);
+        timing_us.clear();
+        session.end = match repeat {
+            0 => step_start + pass,
+            _ => session.start + pass * repeat,
+        };
+
+        for (index, step) in steps.iter().enumerate() {
+            session.index = index as u32;
+            session.step_id = step.id;
+            session.step_start = step_start;
+            session.step_end = step_start + Duration::from_millis(step.time_ms as u64);
+            session.freq = step.freq;
+            session.publish();
+            session.cue(step).await;
+
+            let phase = step.phase.unwrap_or(default_phase);
+            info!(
+                "Setting FREQ to {} over {} ms, phase {}",
+                step.freq, step.time_ms, phase
+            );
+            result = match dds.set_phase(phase) {
+                Some(e) => Err(Halt::Failed(e)),
+                None => {
+                    timing_us.push(lateness_us(session.at(step_start))).ok();
+                    play_step(dds, &mut session, step, mode, step_start, loaded).await
+                }
+            };
+            loaded = Some(session.freq);
+            step_start = session.step_end;
+            if result.is_err() {
+                break;
+            }
+        }
+
+        // Passes of zero-length steps never wait, let the other tasks run
+        yield_now().await;
+    }
+
+    finish(dds, mode, &session, result, gen_completed).await;
+    timing_us
+}
+
+/// Play steps from the streaming buffer as the host sends them, until END.
+async fn generate_stream<D: DdsDevice>(
+    dds: &mut D,
+    ports: DdsPorts<'_>,
+    id: u32,
+    mode: StepMode,
+    default_phase: u8,
+) {
+    info!("Starting streamed DDS operation in {} mode", mode);
+
+    GENERATE_CTRL.reset();
+    ports.at.send(Msg::SetDdsAvailable(false)).await;
+
+    STREAM.clear();
+    STREAM_STATE.lock(|state| {
+        state.set(Some(StreamState {
+            max_freq: dds.max_freq(),
+            ended: false,
+        }))
+    });
+    let streaming = encode_response(b"OPERATION", id, &[b"STREAMING"]);
+    ports
+        .at
+        .send(Msg::SetOperationStatus(status_line(id, streaming)))
+        .await;
+
+    // Build completed response: AT+OPERATION=id#STREAM#credit#COMPLETED
+    let mut credit_buf = [0u8; 10];
+    let credit_len = u32_to_ascii_buf(STREAM_STEPS as u32, &mut credit_buf);
+    let completed = encode_response(
+        b"OPERATION",
+        id,
+        &[b"STREAM", &credit_buf[..credit_len], b"COMPLETED"],
+    );
+    ports.at.send(reply(id, completed)).await;
+
+    let mut result: Result<(), Halt> = Ok(());
+    if mode == StepMode::Continuous {
+        if let Some(e) = dds.reset().await {
+            error!("Error resetting DDS");
+            result = Err(Halt::Failed(e));
+        }
+    }
+
+    let mut session = Session::new(ports, id, 0);
+    session.iteration = 1;
+    // The schedule starts with the first step received
+    let mut step_start: Option<Instant> = None;
+    let mut played: u32 = 0;
+    let mut loaded: Option<MilliHertz> = None;
+    let mut low_water_sent = false;
+
+    while result.is_ok() {
+        let item = match STREAM.try_receive() {
+            Ok(item) => item,
+            Err(_) => {
+                if step_start.is_some() {
+                    warn!("Stream buffer ran empty");
+                    send_stream_notice(ports, id, b"UNDERRUN", None).await;
+                }
+                match wait_stream(ports).await {
+                    Ok(item) => {
+                        // Steps after an underrun are scheduled from their arrival
+                        step_start = None;
+                        item
+                    }
+                    Err(halt) => {
+                        result = Err(halt);
+                        break;
+                    }
+                }
+            }
+        };
+        let StreamItem::Step(step) = item else {
+            info!("Stream ended after {} steps", played);
+            break;
+        };
+
+        let queued = STREAM.len();
+        if queued > STREAM_LOW_WATER {
+            low_water_sent = false;
+        } else if !low_water_sent {
+            low_water_sent = true;
+            send_stream_notice(ports, id, b"CREDIT", Some(STREAM.free_capacity())).await;
+        }
+
+        let start = step_start.unwrap_or_else(|| Instant::now() - session.paused);
+        session.index = played;
+        session.steps = played + 1 + queued as u32;
+        session.step_id = step.id;
+        session.step_start = start;
+        session.step_end = start + Duration::from_millis(step.time_ms as u64);
+        // How much is still to come is up to the host
+        session.end = session.step_end;
+        session.freq = step.freq;
+        session.publish();
+        session.cue(&step).await;
+
+        result = match dds.set_phase(step.phase.unwrap_or(default_phase)) {
+            Some(e) => Err(Halt::Failed(e)),
+            None => play_step(dds, &mut session, &step, mode, start, loaded).await,
+        };
+        loaded = Some(session.freq);
+        step_start = Some(session.step_end);
+        played += 1;
+        yield_now().await;
+    }
+
+    STREAM_STATE.lock(|state| state.set(None));
+    STREAM.clear();
+
+    // Build status: AT+OPERATION=id#STREAM#END#steps#COMPLETED
+    let mut played_buf = [0u8; 10];
+    let played_len = u32_to_ascii_buf(played, &mut played_buf);
+    let completed = encode_response(
+        b"OPERATION",
+        id,
+        &[b"STREAM", b"END", &played_buf[..played_len], b"COMPLETED"],
+    );
+    finish(dds, mode, &session, result, status_line(id, completed)).await;
+}
+
+/// Wait for the host to refill an empty streaming buffer; PAUSE and RESUME
+/// have nothing to act on meanwhile.
+async fn wait_stream(ports: DdsPorts<'_>) -> Result<StreamItem, Halt> {
+    loop {
+        match select(STREAM.receive(), GENERATE_CTRL.wait()).await {
+            Either::First(item) => return Ok(item),
+            Either::Second(GenerateCtrl::Stop { id }) => return Err(Halt::Stopped { id }),
+            Either::Second(ctrl) => {
+                ports
+                    .at
+                    .send(Msg::Err(ctrl.id(), FirmwareError::NotGenerating))
+                    .await;
+            }
+        }
+    }
+}
+
+/// Send `AT+STREAM=id#KIND[#credit]` to the host, unprompted.
+async fn send_stream_notice(ports: DdsPorts<'_>, id: u32, kind: &[u8], credit: Option<usize>) {
+    let mut credit_buf = [0u8; 10];
+    let line = match credit {
+        Some(credit) => {
+            let credit_len = u32_to_ascii_buf(credit as u32, &mut credit_buf);
+            encode_response(b"STREAM", id, &[kind, &credit_buf[..credit_len]])
+        }
+        None => encode_response(b"STREAM", id, &[kind]),
+    };
+    ports.at.send(reply(id, line)).await;
+}
+
+/// Power down after GENERATE or STREAM, release the DDS and report how the
+/// session ended; `completed` becomes the status of a session that ran to the end.
+async fn finish<D: DdsDevice>(
+    dds: &mut D,
+    mode: StepMode,
+    session: &Session<'_>,
+    result: Result<(), Halt>,
+    completed: MsgString,
+) {
+    let ports = session.ports;
+    let id = session.id;
+    PROGRESS.lock(|progress| progress.set(None));
+
+    // A step cut short in RESET mode has left the chip powered up as well
+    if (mode == StepMode::Continuous || result.is_err()) && dds.down().await.is_some() {
+        error!("Error powering down DDS");
+    }
+
+    if session.cued {
+        let Rgb { r, g, b } = host_rgb();
+        ports.rgb.send(Msg::RgbSet { id: None, r, g, b }).await;
+    }
+
+    info!("Setting Device Available to true");
+    ports.at.send(Msg::SetDdsAvailable(true)).await;
+    info!("Set Device Available to true");
+
+    match result {
+        Ok(()) => {
+            ports.at.send(Msg::SetOperationStatus(completed)).await;
+            // A request that raced the last step has nothing left to act on
+            if let Some(ctrl) = GENERATE_CTRL.try_take() {
+                ports
+                    .at
+                    .send(Msg::Err(ctrl.id(), FirmwareError::NotGenerating))
+                    .await;
+            }
+        }
+        Err(Halt::Failed(e)) => {
+            error!("DDS operation failed: {}", e);
+            ports.at.send(Msg::Err(id, e)).await;
+
+            let error_status = encode_error_response(id, &e);
+            ports.at.send(Msg::SetOperationStatus(error_status)).await;
+        }
+        Err(Halt::Stopped { id: stop_id }) => {
+            info!("DDS operation stopped at step {}", session.step_id);
+            let mut sid_buf = [0u8; 10];
+            let sid_len = u32_to_ascii_buf(session.step_id, &mut sid_buf);
+
+            // Build response: AT+OPERATION=stop_id#STOP#step_id#COMPLETED
+            let completed = encode_response(
+                b"OPERATION",
+                stop_id,
+                &[b"STOP", &sid_buf[..sid_len], b"COMPLETED"],
+            );
+            ports.at.send(reply(stop_id, completed)).await;
+
+            // Build status: AT+OPERATION=id#STOPPED#step_id#iteration
+            let mut iter_buf = [0u8; 10];
+            let iter_len = u32_to_ascii_buf(session.iteration, &mut iter_buf);
+            let status = encode_response(
+                b"OPERATION",
+                id,
+                &[b"STOPPED", &sid_buf[..sid_len], &iter_buf[..iter_len]],
+            );
+            ports
+                .at
+                .send(Msg::SetOperationStatus(status_line(id, status)))
+                .await;
+        }
+    }
+}
+
+/// Position and schedule of a running GENERATE.
+///
+/// Deadlines are kept in session time, which stands still while paused;
+/// `at` maps them to wall-clock instants.
+struct Session<'a> {
+    ports: DdsPorts<'a>,
+    id: u32,
+    start: Instant,
+    /// Current pass over the steps, from 1
+    iteration: u32,
+    /// 0-based position of the current step, out of `steps`
+    index: u32,
+    steps: u32,
+    step_id: u32,
+    /// Session time at which the current step started
+    step_start: Instant,
+    /// Session time at which the current step ends
+    step_end: Instant,
+    /// Session time at which the operation, or the current pass when repeating
+    /// until stopped, ends
+    end: Instant,
+    /// Time spent paused so far
+    paused: Duration,
+    /// Frequency and tuning word last loaded, the tuning word is restored on RESUME
+    freq: MilliHertz,
+    ftw: u32,
+    /// A step colour has been shown, the LED goes back to the SETRGB colour at the end
+    cued: bool,
+}
+
+impl<'a> Session<'a> {
+    fn new(ports: DdsPorts<'a>, id: u32, steps: u32) -> Self {
+        let start = Instant::now();
+        Self {
+            ports,
+            id,
+            start,
+            iteration: 0,
+            index: 0,
+            steps,
+            step_id: 0,
+            step_start: start,
+            step_end: start,
+            end: start,
+            paused: Duration::from_ticks(0),
+            freq: MilliHertz(0),
+            ftw: 0,
+            cued: false,
+        }
+    }
+
+    fn at(&self, t: Instant) -> Instant {
+        t + self.paused
+    }
+
+    /// Make the current position visible to `AT+OPERATION?`.
+    fn publish(&self) {
+        let progress = Progress {
+            id: self.id,
+            step_id: self.step_id,
+            iteration: self.iteration,
+            index: self.index,
+            steps: self.steps,
+            start: self.at(self.start),
+            step_start: self.at(self.step_start),
+            step_end: self.at(self.step_end),
+            end: self.at(self.end),
+            freq: self.freq,
+        };
+        PROGRESS.lock(|cell| cell.set(Some(progress)));
+    }
+
+    /// Show the colour of `step` on the RGB LED, if it has one.
+    async fn cue(&mut self, step: &FreqStep) {
+        if let Some(Rgb { r, g, b }) = step.colour {
+            self.ports.rgb.send(Msg::RgbSet { id: None, r, g, b }).await;
+            self.cued = true;
+        }
+    }
+
+    async fn load<D: DdsDevice>(&mut self, dds: &mut D, freq: MilliHertz) -> Result<(), Halt> {
+        let ftw = dds.freq_to_ftw(freq);
+        check(dds.load_ftw(ftw).await)?;
+        self.ftw = ftw;
+        self.freq = freq;
+        self.publish();
+        Ok(())
+    }
+
+    /// Wait for session time `until`, handling PAUSE, RESUME and STOP on the way.
+    async fn dwell<D: DdsDevice>(&mut self, dds: &mut D, until: Instant) -> Result<(), Halt> {
+        loop {
+            match select(Timer::at(self.at(until)), GENERATE_CTRL.wait()).await {
+                Either::First(()) => return Ok(()),
+                Either::Second(GenerateCtrl::Stop { id }) => return Err(Halt::Stopped { id }),
+                Either::Second(GenerateCtrl::Pause { id }) => self.pause(dds, id).await?,
+                Either::Second(GenerateCtrl::Resume { id }) => {
+                    self.ports
+                        .at
+                        .send(Msg::Err(id, FirmwareError::NotPaused))
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Power down until RESUME, then restore the output and shift the schedule.
+    async fn pause<D: DdsDevice>(&mut self, dds: &mut D, pause_id: u32) -> Result<(), Halt> {
+        let paused_at = Instant::now();
+        PROGRESS.lock(|progress| progress.set(None));
+        check(dds.down().await)?;
+
+        let remaining = self.at(self.step_end).saturating_duration_since(paused_at);
+        let mut sid_buf = [0u8; 10];
+        let sid_len = u32_to_ascii_buf(self.step_id, &mut sid_buf);
+        let mut rem_buf = [0u8; 10];
+        let rem_len = u32_to_ascii_buf(remaining.as_millis() as u32, &mut rem_buf);
+        info!(
+            "DDS operation paused at step {}, {} ms left",
+            self.step_id,
+            remaining.as_millis()
+        );
+
+        // Build status: AT+OPERATION=id#PAUSED#step_id#remaining_ms#iteration
+        let mut iter_buf = [0u8; 10];
+        let iter_len = u32_to_ascii_buf(self.iteration, &mut iter_buf);
+        let status = encode_response(
+            b"OPERATION",
+            self.id,
+            &[
+                b"PAUSED",
+                &sid_buf[..sid_len],
+                &rem_buf[..rem_len],
+                &iter_buf[..iter_len],
+            ],
+        );
+        self.ports
+            .at
+            .send(Msg::SetOperationStatus(status_line(self.id, status)))
+            .await;
+
+        let mut request_id = pause_id;
+        let resume_id = loop {
+            // Build response: AT+OPERATION=pause_id#PAUSE#step_id#remaining_ms#COMPLETED
+            let completed = encode_response(
+                b"OPERATION",
+                request_id,
+                &[
+                    b"PAUSE",
+                    &sid_buf[..sid_len],
+                    &rem_buf[..rem_len],
+                    b"COMPLETED",
+                ],
+            );
+            self.ports.at.send(reply(request_id, completed)).await;
+
+            match GENERATE_CTRL.wait().await {
+                GenerateCtrl::Resume { id } => break id,
+                GenerateCtrl::Stop { id } => return Err(Halt::Stopped { id }),
+                // Already paused, report the same position again
+                GenerateCtrl::Pause { id } => request_id = id,
+            }
+        };
+
+        self.paused += Instant::now() - paused_at;
+        check(dds.restart().await)?;
+        check(dds.load_ftw(self.ftw).await)?;
+        info!("DDS operation resumed at step {}", self.step_id);
+
+        // Build response: AT+OPERATION=resume_id#RESUME#step_id#remaining_ms#COMPLETED
+        let completed = encode_response(
+            b"OPERATION",
+            resume_id,
+            &[
+                b"RESUME",
+                &sid_buf[..sid_len],
+                &rem_buf[..rem_len],
+                b"COMPLETED",
+            ],
+        );
+        self.ports.at.send(reply(resume_id, completed)).await;
+        self.publish();
+        Ok(())
+    }
+}
+
+fn check(err: Option<FirmwareError>) -> Result<(), Halt> {
+    match err {
+        Some(e) => Err(Halt::Failed(e)),
+        None => Ok(()),
+    }
+}
+
+/// Play one step of an operation scheduled at session time `start`, expanding
+/// sweeps into tuning word updates. The step ends at `start + time_ms` however
+/// long the chip setup took.
+async fn play_step<D: DdsDevice>(
+    dds: &mut D,
+    session: &mut Session<'_>,
+    step: &FreqStep,
+    mode: StepMode,
+    start: Instant,
+    from: Option<MilliHertz>,
+) -> Result<(), Halt> {
+    if mode == StepMode::Reset {
+        check(dds.restart().await)?;
+    }
+
+    // A glide takes the start of the step time, so the step still ends on schedule
+    let mut start = start;
+    let mut time_ms = step.time_ms;
+    if let (Some(glide), Some(from)) = (step.glide, from) {
+        let glide_ms = glide.time_ms.min(time_ms);
+        if glide_ms > 0 {
+            let updates = Glide::updates(glide_ms);
+            let freq_at = |k| glide.freq_at(from, step.freq, k, updates);
+            ramp(dds, session, updates, freq_at, glide_ms, start).await?;
+            start += Duration::from_millis(glide_ms as u64);
+            time_ms -= glide_ms;
+        }
+    }
+
+    match step.sweep {
+        None => {
+            session.load(dds, step.freq).await?;
+            session
+                .dwell(dds, start + Duration::from_millis(time_ms as u64))
+                .await?;
+        }
+        Some(sweep) => {
+            let updates = sweep.updates(time_ms);
+            let freq_at = |k| sweep.freq_at(step.freq, k, updates);
+            ramp(dds, session, updates, freq_at, time_ms, start).await?;
+        }
+    }
+
+    match mode {
+        StepMode::Continuous => Ok(()),
+        StepMode::Reset => check(dds.down().await),
+    }
+}
+
+/// Load `freq_at(k)` for each of `updates` evenly spaced over `time_ms` from `start`.
+async fn ramp<D: DdsDevice>(
+    dds: &mut D,
+    session: &mut Session<'_>,
+    updates: u32,
+    freq_at: impl Fn(u32) -> MilliHertz,
+    time_ms: u32,
+    start: Instant,
+) -> Result<(), Halt> {
+    // Spread the time over the updates so the ramp lasts exactly time_ms
+    let total = time_ms as u64;
+    for k in 0..updates {
+        session.load(dds, freq_at(k)).await?;
+        let until = start + Duration::from_millis(total * (k as u64 + 1) / updates as u64);
+        session.dwell(dds, until).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::future::Future;
+    use std::format;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_futures::select::select3;
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as Cs;
+    use embassy_sync::channel::Channel;
+
+    use super::*;
+    use crate::dds::mock::{MockBus, MockOutput};
+    use crate::serial;
+    use crate::storage::{RamFlash, SlotName, init_storage, load_config};
+    use crate::usb::{BULK_MAX_STEPS, BulkChunk, queue_bulk};
+    use chip::*;
+    // Not the defmt ones from `super`
+    use core::{assert, assert_eq, assert_ne, panic};
+
+    /// The backend the library is built for.
+    #[cfg(not(feature = "ad983x"))]
+    mod chip {
+        use super::*;
+
+        pub const CLK_HZ: u32 = 125_000_000;
+        /// round(1 kHz * 2^32 / 125 MHz)
+        pub const FTW_1KHZ: u32 = 34_360;
+        pub const FTW_2KHZ: u32 = 68_719;
+        /// What 1 kHz comes out at, and its error in ppm
+        pub const ACHIEVED_1KHZ: &str = "1000.008#8";
+        pub const PWRDOWN: u8 = 1 << 2;
+
+        pub fn chip(bus: &MockBus) -> impl DdsDevice + '_ {
+            bus.ad985x(DdsVariant::Ad9850, CLK_HZ)
+        }
+    }
+
+    #[cfg(feature = "ad983x")]
+    mod chip {
+        use super::*;
+
+        pub const CLK_HZ: u32 = 25_000_000;
+        /// round(1 kHz * 2^28 / 25 MHz)
+        pub const FTW_1KHZ: u32 = 10_737;
+        pub const FTW_2KHZ: u32 = 21_475;
+        pub const ACHIEVED_1KHZ: &str = "999.961#-39";
+
+        pub fn chip(bus: &MockBus) -> impl DdsDevice + '_ {
+            bus.ad983x(CLK_HZ)
+        }
+    }
+
+    fn on(ftw: u32, phase: u8) -> MockOutput {
+        MockOutput::On { ftw, phase }
+    }
+
+    /// Feed `commands` to `run_dds` on the chip wired to `bus` and collect
+    /// everything it sends to the AT and RGB tasks.
+    fn drive(bus: &MockBus, commands: impl IntoIterator<Item = Msg>) -> Vec<Msg> {
+        drive_dds(&mut chip(bus), commands)
+    }
+
+    fn drive_dds<D: DdsDevice>(dds: &mut D, commands: impl IntoIterator<Item = Msg>) -> Vec<Msg> {
+        let sent = RefCell::new(Vec::new());
+        drive_with(dds, commands, &sent, async {});
+        sent.into_inner()
+    }
+
+    /// `drive_dds` with `script` running alongside, to act on what has been
+    /// `sent` so far while an operation plays.
+    fn drive_with<D: DdsDevice>(
+        dds: &mut D,
+        commands: impl IntoIterator<Item = Msg>,
+        sent: &RefCell<Vec<Msg>>,
+        script: impl Future<Output = ()>,
+    ) {
+        let rx: Channel<Cs, Msg, CAP> = Channel::new();
+        let at: Channel<Cs, Msg, CAP> = Channel::new();
+        let rgb: Channel<Cs, Msg, CAP> = Channel::new();
+        for msg in commands {
+            rx.try_send(msg).ok().unwrap();
+        }
+        // run_dds returns on the first message it does not handle
+        rx.try_send(Msg::Done(0)).ok().unwrap();
+
+        let ports = DdsPorts {
+            rx: rx.receiver(),
+            at: at.sender(),
+            rgb: rgb.sender(),
+        };
+        let collect = async {
+            loop {
+                let msg = select(at.receive(), rgb.receive()).await;
+                let (Either::First(msg) | Either::Second(msg)) = msg;
+                sent.borrow_mut().push(msg);
+            }
+        };
+        let timeout = async {
+            Timer::after_secs(10).await;
+            panic!("run_dds did not return");
+        };
+        block_on(join(select3(run_dds(dds, ports), collect, timeout), script));
+        while let Ok(msg) = at.try_receive().or_else(|_| rgb.try_receive()) {
+            sent.borrow_mut().push(msg);
+        }
+    }
+
+    /// Wait in a `drive_with` script until `done` holds.
+    async fn until(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out in a test script");
+            yield_now().await;
+        }
+    }
+
+    fn responses(sent: &[Msg]) -> Vec<&str> {
+        sent.iter()
+            .filter_map(|msg| match msg {
+                Msg::AtCmdResponse(line) => Some(line.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn statuses(sent: &[Msg]) -> Vec<&str> {
+        sent.iter()
+            .filter_map(|msg| match msg {
+                Msg::SetOperationStatus(line) => Some(line.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn availability(sent: &[Msg]) -> Vec<bool> {
+        sent.iter()
+            .filter_map(|msg| match msg {
+                Msg::SetDdsAvailable(available) => Some(*available),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The response so far starting with `prefix`.
+    fn response_to(sent: &RefCell<Vec<Msg>>, prefix: &str) -> Option<std::string::String> {
+        responses(&sent.borrow())
+            .into_iter()
+            .find(|line| line.starts_with(prefix))
+            .map(Into::into)
+    }
+
+    fn prepare(id: u32) -> Msg {
+        Msg::OperationCmd {
+            id,
+            sub: OperationSub::Prepare { repeat: 1 },
+        }
+    }
+
+    fn freq(id: u32, hz: u32, time_ms: u32, phase: Option<u8>) -> Msg {
+        Msg::FreqSet {
+            id,
+            freq: MilliHertz::from_hz(hz),
+            time_ms,
+            phase,
+            glide: None,
+            colour: None,
+        }
+    }
+
+    fn generate(id: u32, mode: StepMode) -> Msg {
+        Msg::OperationCmd {
+            id,
+            sub: OperationSub::Generate {
+                mode,
+                slot: None,
+                operation_id: None,
+            },
+        }
+    }
+
+    /// Id of the step a GENERATE is on, `None` while nothing plays.
+    fn playing_step() -> Option<u32> {
+        PROGRESS.lock(|progress| progress.get()).map(|p| p.step_id)
+    }
+
+    #[test]
+    fn prepare_and_freq_leave_the_bus_alone() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = drive(&bus, [prepare(1), freq(2, 1000, 10, None)]);
+
+        assert!(bus.events().is_empty());
+        assert_eq!(
+            responses(&sent),
+            [
+                "AT+OPERATION=1#PREPARE#COMPLETED",
+                &format!("AT+FREQ=2#1000#10#{ACHIEVED_1KHZ}#COMPLETED")
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_continuous_loads_every_step_once() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = drive(
+            &bus,
+            [
+                prepare(1),
+                freq(2, 1000, 10, None),
+                freq(3, 2000, 10, Some(8)),
+                generate(4, StepMode::Continuous),
+            ],
+        );
+
+        // Reset, the steps without a reset in between, then power down
+        assert_eq!(
+            bus.outputs().as_slice(),
+            [
+                MockOutput::Reset,
+                on(0, 0),
+                on(FTW_1KHZ, 0),
+                on(FTW_2KHZ, 8),
+                MockOutput::Off
+            ]
+        );
+        assert_eq!(
+            statuses(&sent),
+            [
+                "AT+OPERATION=1#PREPARE#COMPLETED",
+                "AT+OPERATION=4#GENERATE#COMPLETED",
+                "AT+OPERATION=4#GENERATE#COMPLETED"
+            ]
+        );
+        assert!(matches!(sent.last(), Some(Msg::SetOperationStatus(_))));
+    }
+
+    #[test]
+    fn generate_reset_power_cycles_every_step() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        drive(
+            &bus,
+            [
+                prepare(1),
+                freq(2, 1000, 5, Some(8)),
+                freq(3, 2000, 5, None),
+                generate(4, StepMode::Reset),
+            ],
+        );
+
+        let restart = [MockOutput::Off, MockOutput::Reset, on(0, 0)];
+        let mut expected = Vec::new();
+        expected.extend(restart);
+        expected.extend([on(FTW_1KHZ, 8), MockOutput::Off]);
+        expected.extend(restart);
+        expected.extend([on(FTW_2KHZ, 0), MockOutput::Off]);
+        assert_eq!(bus.outputs().as_slice(), expected.as_slice());
+    }
+
+    #[cfg(not(feature = "ad983x"))]
+    #[test]
+    fn generate_continuous_writes_exact_words() {
+        use crate::dds::mock::MockEvent;
+
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = drive(
+            &bus,
+            [
+                prepare(1),
+                freq(2, 1000, 10, None),
+                generate(3, StepMode::Continuous),
+            ],
+        );
+
+        // Reset into serial mode, the step, then power down
+        assert_eq!(
+            bus.events().as_slice(),
+            [
+                MockEvent::Reset,
+                MockEvent::Strobe { bits: 5 },
+                MockEvent::Word { ftw: 0, ctrl: 0 },
+                MockEvent::Word {
+                    ftw: FTW_1KHZ,
+                    ctrl: 0
+                },
+                MockEvent::Word {
+                    ftw: 0,
+                    ctrl: PWRDOWN
+                },
+            ]
+        );
+        assert!(sent.iter().any(|msg| matches!(
+            msg,
+            Msg::SetOperationStatus(status) if status == "AT+OPERATION=3#GENERATE#COMPLETED"
+        )));
+        assert!(matches!(sent.last(), Some(Msg::SetOperationStatus(_))));
+    }
+
+    #[cfg(not(feature = "ad983x"))]
+    #[test]
+    fn generate_reset_power_cycles_every_step_with_its_phase() {
+        use crate::dds::mock::MockEvent;
+
+        let _serial = serial();
+        let bus = MockBus::new();
+        drive(
+            &bus,
+            [
+                prepare(1),
+                freq(2, 1000, 5, Some(8)),
+                freq(3, 2000, 5, None),
+                generate(4, StepMode::Reset),
+            ],
+        );
+
+        let restart = [
+            MockEvent::Word {
+                ftw: 0,
+                ctrl: PWRDOWN,
+            },
+            MockEvent::Word { ftw: 0, ctrl: 0 },
+            MockEvent::Reset,
+            MockEvent::Strobe { bits: 5 },
+            MockEvent::Word { ftw: 0, ctrl: 0 },
+        ];
+        let mut expected = Vec::new();
+        expected.extend(restart);
+        // Phase 8 sits in control bits W35..W39
+        expected.push(MockEvent::Word {
+            ftw: FTW_1KHZ,
+            ctrl: 8 << 3,
+        });
+        expected.push(restart[0]);
+        expected.extend(restart);
+        expected.push(MockEvent::Word {
+            ftw: FTW_2KHZ,
+            ctrl: 0,
+        });
+        expected.push(restart[0]);
+        assert_eq!(bus.events().as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn stop_mid_dwell_powers_down_and_names_the_step() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = RefCell::new(Vec::new());
+        let start = Instant::now();
+        drive_with(
+            &mut chip(&bus),
+            [
+                prepare(1),
+                freq(2, 1000, 10, None),
+                freq(3, 2000, 5000, None),
+                generate(4, StepMode::Continuous),
+                Msg::OperationCmd {
+                    id: 5,
+                    sub: OperationSub::Stop,
+                },
+            ],
+            &sent,
+            async {
+                until(|| bus.outputs().last() == Some(&on(FTW_2KHZ, 0))).await;
+                GENERATE_CTRL.signal(GenerateCtrl::Stop { id: 9 });
+            },
+        );
+        let sent = sent.into_inner();
+
+        // Well short of the 5 s step
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(
+            bus.outputs().as_slice(),
+            [
+                MockOutput::Reset,
+                on(0, 0),
+                on(FTW_1KHZ, 0),
+                on(FTW_2KHZ, 0),
+                MockOutput::Off
+            ]
+        );
+        assert_eq!(availability(&sent), [false, true]);
+        assert_eq!(
+            responses(&sent).last(),
+            Some(&"AT+OPERATION=9#STOP#3#COMPLETED")
+        );
+        assert_eq!(statuses(&sent).last(), Some(&"AT+OPERATION=4#STOPPED#3#1"));
+        assert!(playing_step().is_none());
+        // Nothing left to stop
+        assert!(matches!(
+            sent.last(),
+            Some(Msg::Err(5, FirmwareError::NotGenerating))
+        ));
+    }
+
+    #[test]
+    fn pause_powers_down_and_resume_reloads_the_step() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = RefCell::new(Vec::new());
+        let mut remaining_ms = 0;
+        let mut resumed = Instant::now();
+        drive_with(
+            &mut chip(&bus),
+            [
+                prepare(1),
+                freq(2, 1000, 300, Some(4)),
+                generate(3, StepMode::Continuous),
+            ],
+            &sent,
+            async {
+                until(|| bus.outputs().last() == Some(&on(FTW_1KHZ, 4))).await;
+                GENERATE_CTRL.signal(GenerateCtrl::Resume { id: 5 });
+                until(|| {
+                    let sent = sent.borrow();
+                    matches!(sent.last(), Some(Msg::Err(5, FirmwareError::NotPaused)))
+                })
+                .await;
+
+                GENERATE_CTRL.signal(GenerateCtrl::Pause { id: 6 });
+                until(|| response_to(&sent, "AT+OPERATION=6#PAUSE#").is_some()).await;
+                let paused = response_to(&sent, "AT+OPERATION=6#PAUSE#").unwrap();
+                let fields: Vec<&str> = paused.split('#').collect();
+                assert_eq!(fields[2], "2");
+                remaining_ms = fields[3].parse().unwrap();
+                assert!((1..=300).contains(&remaining_ms));
+
+                // AT+OPERATION? has no live position and falls back to the status
+                assert_eq!(bus.outputs().last(), Some(&MockOutput::Off));
+                assert!(progress_status().is_none());
+                let status = format!("AT+OPERATION=3#PAUSED#2#{remaining_ms}#1");
+                assert_eq!(statuses(&sent.borrow()).last(), Some(&status.as_str()));
+
+                Timer::after_millis(100).await;
+                resumed = Instant::now();
+                GENERATE_CTRL.signal(GenerateCtrl::Resume { id: 7 });
+            },
+        );
+        let sent = sent.into_inner();
+
+        // The rest of the step plays from RESUME
+        let finished = resumed.elapsed();
+        assert!(finished >= Duration::from_millis(remaining_ms));
+        assert!(finished < Duration::from_millis(remaining_ms + 250));
+        assert_eq!(
+            responses(&sent).last(),
+            Some(&format!("AT+OPERATION=7#RESUME#2#{remaining_ms}#COMPLETED").as_str())
+        );
+        // Power-cycled back onto the same tuning word and phase
+        assert_eq!(
+            bus.outputs().as_slice(),
+            [
+                MockOutput::Reset,
+                on(0, 0),
+                on(FTW_1KHZ, 4),
+                MockOutput::Off,
+                MockOutput::Off,
+                MockOutput::Reset,
+                on(0, 0),
+                on(FTW_1KHZ, 4),
+                MockOutput::Off
+            ]
+        );
+        assert_eq!(
+            statuses(&sent).last(),
+            Some(&"AT+OPERATION=3#GENERATE#COMPLETED")
+        );
+    }
+
+    fn prepare_repeat(id: u32, repeat: u32) -> Msg {
+        Msg::OperationCmd {
+            id,
+            sub: OperationSub::Prepare { repeat },
+        }
+    }
+
+    #[test]
+    fn repeat_plays_the_steps_that_many_times() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = drive(
+            &bus,
+            [
+                prepare_repeat(1, 3),
+                freq(2, 1000, 2, None),
+                freq(3, 2000, 2, None),
+                generate(4, StepMode::Continuous),
+            ],
+        );
+
+        let mut expected = Vec::from([MockOutput::Reset, on(0, 0)]);
+        for _ in 0..3 {
+            expected.extend([on(FTW_1KHZ, 0), on(FTW_2KHZ, 0)]);
+        }
+        expected.push(MockOutput::Off);
+        assert_eq!(bus.outputs().as_slice(), expected.as_slice());
+        assert_eq!(
+            statuses(&sent).last(),
+            Some(&"AT+OPERATION=4#GENERATE#COMPLETED")
+        );
+    }
+
+    #[test]
+    fn repeat_0_loops_until_stopped() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = RefCell::new(Vec::new());
+        drive_with(
+            &mut chip(&bus),
+            [
+                prepare_repeat(1, 0),
+                freq(2, 1000, 2, None),
+                freq(3, 2000, 2, None),
+                generate(4, StepMode::Continuous),
+            ],
+            &sent,
+            async {
+                until(|| PROGRESS.lock(|p| p.get()).is_some_and(|p| p.iteration == 3)).await;
+                GENERATE_CTRL.signal(GenerateCtrl::Stop { id: 5 });
+            },
+        );
+        let sent = sent.into_inner();
+
+        let status = *statuses(&sent).last().unwrap();
+        assert!(status.starts_with("AT+OPERATION=4#STOPPED#"));
+        assert!(status.ends_with("#3"));
+        let outputs = bus.outputs();
+        assert_eq!(
+            outputs[..6],
+            [
+                MockOutput::Reset,
+                on(0, 0),
+                on(FTW_1KHZ, 0),
+                on(FTW_2KHZ, 0),
+                on(FTW_1KHZ, 0),
+                on(FTW_2KHZ, 0)
+            ]
+        );
+        assert_eq!(outputs.last(), Some(&MockOutput::Off));
+    }
+
+    fn arm(id: u32, delay_ms: u32) -> Msg {
+        Msg::OperationCmd {
+            id,
+            sub: OperationSub::Arm {
+                delay_ms,
+                mode: StepMode::Continuous,
+                slot: None,
+                operation_id: None,
+            },
+        }
+    }
+
+    #[test]
+    fn arm_starts_after_its_delay() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let start = Instant::now();
+        let sent = drive(&bus, [prepare(1), freq(2, 1000, 5, None), arm(3, 50)]);
+
+        assert!(start.elapsed() >= Duration::from_millis(55));
+        assert_eq!(
+            responses(&sent).last(),
+            Some(&"AT+OPERATION=3#ARM#50#COMPLETED")
+        );
+        assert_eq!(
+            bus.outputs().as_slice(),
+            [
+                MockOutput::Reset,
+                on(0, 0),
+                on(FTW_1KHZ, 0),
+                MockOutput::Off
+            ]
+        );
+        assert_eq!(
+            statuses(&sent).last(),
+            Some(&"AT+OPERATION=3#GENERATE#COMPLETED")
+        );
+    }
+
+    #[test]
+    fn arm_cancelled_before_its_deadline_writes_nothing() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = RefCell::new(Vec::new());
+        let start = Instant::now();
+        drive_with(
+            &mut chip(&bus),
+            [prepare(1), freq(2, 1000, 10, None), arm(3, 10_000)],
+            &sent,
+            async {
+                until(|| response_to(&sent, "AT+OPERATION=3#ARM#").is_some()).await;
+                let armed = progress_status().unwrap();
+                assert!(armed.starts_with("AT+OPERATION=3#ARMED#"));
+
+                // Nothing plays yet to pause
+                GENERATE_CTRL.signal(GenerateCtrl::Pause { id: 4 });
+                until(|| {
+                    let sent = sent.borrow();
+                    matches!(sent.last(), Some(Msg::Err(4, FirmwareError::NotGenerating)))
+                })
+                .await;
+                GENERATE_CTRL.signal(GenerateCtrl::Stop { id: 5 });
+            },
+        );
+        let sent = sent.into_inner();
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(bus.events().is_empty());
+        assert_eq!(availability(&sent), [false, true]);
+        assert_eq!(
+            responses(&sent)[2..],
+            [
+                "AT+OPERATION=3#ARM#10000#COMPLETED",
+                "AT+OPERATION=5#STOP#ARMED#COMPLETED"
+            ]
+        );
+        assert_eq!(statuses(&sent).last(), Some(&"AT+OPERATION=3#DISARMED"));
+        assert!(progress_status().is_none());
+    }
+
+    fn stream_step(id: u32, hz: u32) -> StreamItem {
+        StreamItem::Step(FreqStep {
+            id,
+            freq: MilliHertz::from_hz(hz),
+            time_ms: 0,
+            phase: None,
+            sweep: None,
+            glide: None,
+            colour: None,
+        })
+    }
+
+    #[test]
+    fn stream_asks_for_more_then_reports_the_underrun() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = RefCell::new(Vec::new());
+        let stream = Msg::OperationCmd {
+            id: 3,
+            sub: OperationSub::Stream {
+                mode: StepMode::Continuous,
+            },
+        };
+        drive_with(&mut chip(&bus), [stream], &sent, async {
+            until(stream_open).await;
+            // Fill the whole buffer before playback takes the first step
+            for i in 0..STREAM_STEPS as u32 {
+                let credit = stream_push(stream_step(100 + i, 1000 + i % 2 * 1000));
+                assert_eq!(credit.ok(), Some(STREAM_STEPS - 1 - i as usize));
+            }
+            assert!(matches!(
+                stream_push(stream_step(999, 1000)),
+                Err(FirmwareError::StreamFull)
+            ));
+
+            until(|| response_to(&sent, "AT+STREAM=3#UNDERRUN").is_some()).await;
+            assert_eq!(stream_push(StreamItem::End).ok(), Some(STREAM_STEPS - 1));
+        });
+        let sent = sent.into_inner();
+
+        // CREDIT once the buffer is down to a quarter, UNDERRUN once it is empty
+        assert_eq!(
+            responses(&sent),
+            [
+                "AT+OPERATION=3#STREAM#128#COMPLETED",
+                "AT+STREAM=3#CREDIT#96",
+                "AT+STREAM=3#UNDERRUN"
+            ]
+        );
+        assert_eq!(
+            statuses(&sent),
+            [
+                "AT+OPERATION=3#STREAMING",
+                "AT+OPERATION=3#STREAM#END#128#COMPLETED"
+            ]
+        );
+        let outputs = bus.outputs();
+        assert_eq!(outputs.len(), 2 + STREAM_STEPS + 1);
+        assert_eq!(outputs[2..4], [on(FTW_1KHZ, 0), on(FTW_2KHZ, 0)]);
+        assert_eq!(outputs.last(), Some(&MockOutput::Off));
+        assert!(!stream_open());
+    }
+
+    fn bulk_chunk(id: u32, steps: u32) -> BulkChunk {
+        let mut chunk = BulkChunk {
+            id,
+            steps: heapless::Vec::new(),
+        };
+        for i in 0..steps {
+            let step = FreqStep {
+                id: id + i,
+                freq: MilliHertz::from_hz(1000),
+                time_ms: 10,
+                phase: None,
+                sweep: None,
+                glide: None,
+                colour: None,
+            };
+            chunk.steps.push(step).ok().unwrap();
+        }
+        chunk
+    }
+
+    #[test]
+    fn bulk_chunks_are_appended_by_id() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        queue_bulk(bulk_chunk(500, 2)).unwrap();
+        queue_bulk(bulk_chunk(600, 1)).unwrap();
+        let sent = drive(&bus, [prepare(1), Msg::BulkChunk(600), Msg::BulkChunk(500)]);
+
+        assert_eq!(
+            responses(&sent)[1..],
+            ["AT+BULK=600#ACK#1", "AT+BULK=500#ACK#3"]
+        );
+        let ids: Vec<u32> = OPERATION
+            .try_lock()
+            .unwrap()
+            .borrow()
+            .get_steps()
+            .iter()
+            .map(|step| step.id)
+            .collect();
+        assert_eq!(ids, [600, 500, 501]);
+    }
+
+    #[test]
+    fn bulk_chunks_feed_an_open_stream_past_the_operation_limit() {
+        let _serial = serial();
+        assert_eq!(stream_bulk(700).unwrap(), "AT+BULK=700#NAK#15");
+        queue_bulk(bulk_chunk(700, 16)).unwrap();
+        assert_eq!(stream_bulk(700).unwrap(), "AT+BULK=700#NAK#25");
+
+        STREAM_STATE.lock(|state| {
+            state.set(Some(StreamState {
+                max_freq: MilliHertz::from_hz(62_500_000),
+                ended: false,
+            }))
+        });
+        assert!(stream_open());
+        // 160 steps in chunks of 16, well past OPERATION_STEPS, as playback drains them
+        let mut acks = Vec::new();
+        for chunk in 0..10 {
+            let id = 1000 + chunk * BULK_MAX_STEPS as u32;
+            queue_bulk(bulk_chunk(id, BULK_MAX_STEPS as u32)).unwrap();
+            acks.push(stream_bulk(id).unwrap());
+            while STREAM.len() > 64 {
+                STREAM.try_receive().ok().unwrap();
+            }
+        }
+        assert_eq!(acks[0], "AT+BULK=1000#ACK#112");
+        assert_eq!(acks[9], "AT+BULK=1144#ACK#48");
+
+        // A chunk that does not fit is refused whole
+        while STREAM.free_capacity() > 8 {
+            queue_bulk(bulk_chunk(3000, 1)).unwrap();
+            stream_bulk(3000).unwrap();
+        }
+        queue_bulk(bulk_chunk(4000, 16)).unwrap();
+        assert_eq!(stream_bulk(4000).unwrap(), "AT+BULK=4000#NAK#30");
+        assert_eq!(STREAM.free_capacity(), 8);
+
+        STREAM_STATE.lock(|state| state.set(None));
+        STREAM.clear();
+    }
+
+    /// Chip whose tuning word loads fail on the bus.
+    struct FailingLoad<D>(D);
+
+    impl<D: DdsDevice> DdsDevice for FailingLoad<D> {
+        async fn reset(&mut self) -> Option<FirmwareError> {
+            self.0.reset().await
+        }
+        async fn down(&mut self) -> Option<FirmwareError> {
+            self.0.down().await
+        }
+        async fn up(&mut self) -> Option<FirmwareError> {
+            self.0.up().await
+        }
+        async fn load_ftw(&mut self, _ftw: u32) -> Option<FirmwareError> {
+            Some(FirmwareError::DdsBus)
+        }
+        fn set_phase(&mut self, phase: u8) -> Option<FirmwareError> {
+            self.0.set_phase(phase)
+        }
+        fn set_clock_ppb(&mut self, ppb: i32) {
+            self.0.set_clock_ppb(ppb)
+        }
+        fn clock_ppb(&self) -> i32 {
+            self.0.clock_ppb()
+        }
+        fn freq_to_ftw(&self, freq: MilliHertz) -> u32 {
+            self.0.freq_to_ftw(freq)
+        }
+        fn ftw_to_freq(&self, ftw: u32) -> MilliHertz {
+            self.0.ftw_to_freq(ftw)
+        }
+        fn max_freq(&self) -> MilliHertz {
+            self.0.max_freq()
+        }
+    }
+
+    #[test]
+    fn generate_reports_the_dds_error() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let mut dds = FailingLoad(chip(&bus));
+        let sent = drive_dds(
+            &mut dds,
+            [
+                prepare(1),
+                freq(2, 1000, 10, None),
+                generate(3, StepMode::Continuous),
+            ],
+        );
+
+        assert!(
+            sent.iter()
+                .any(|msg| matches!(msg, Msg::Err(3, FirmwareError::DdsBus)))
+        );
+        assert!(matches!(
+            sent.last(),
+            Some(Msg::SetOperationStatus(status)) if status == "AT+ERROR=3#22"
+        ));
+    }
+
+    #[test]
+    fn worst_case_generating_status_fits() {
+        let progress = Progress {
+            id: u32::MAX,
+            step_id: u32::MAX,
+            iteration: u32::MAX,
+            index: u32::MAX,
+            steps: u32::MAX,
+            start: Instant::MIN,
+            step_start: Instant::MIN,
+            step_end: Instant::MAX,
+            end: Instant::MAX,
+            freq: MilliHertz(u32::MAX as u64 * 1000 + 999),
+        };
+        let now = Instant::from_ticks(u64::MAX / 2);
+
+        let line = progress.status(now).unwrap();
+        assert!(line.starts_with("AT+OPERATION=4294967295#GENERATING#4294967295#"));
+        assert!(line.ends_with("#4294967295.999#COMPLETED"));
+    }
+
+    #[test]
+    fn generate_refuses_another_operation() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let generate_op = |id, operation_id| Msg::OperationCmd {
+            id,
+            sub: OperationSub::Generate {
+                mode: StepMode::Continuous,
+                slot: None,
+                operation_id: Some(operation_id),
+            },
+        };
+        let sent = drive(
+            &bus,
+            [prepare(1), freq(2, 1000, 10, None), generate_op(3, 9)],
+        );
+
+        assert!(bus.events().is_empty());
+        assert!(matches!(
+            sent.last(),
+            Some(Msg::Err(3, FirmwareError::OperationMismatch))
+        ));
+
+        let sent = drive(&bus, [generate_op(4, 1)]);
+        assert!(!bus.events().is_empty());
+        assert!(matches!(
+            sent.last(),
+            Some(Msg::SetOperationStatus(status)) if status == "AT+OPERATION=4#GENERATE#COMPLETED"
+        ));
+    }
+
+    #[test]
+    fn generate_slot_leaves_the_prepared_operation() {
+        let _serial = serial();
+        block_on(init_storage(RamFlash::default()));
+        let bus = MockBus::new();
+        let slot_cmd = |id, sub| Msg::SlotCmd { id, sub };
+        let operation_cmd = |id, sub| Msg::OperationCmd { id, sub };
+        let sent = drive(
+            &bus,
+            [
+                prepare(1),
+                freq(2, 1000, 10, None),
+                slot_cmd(
+                    3,
+                    SlotSub::Store {
+                        slot: 0,
+                        name: SlotName::try_from("one").unwrap(),
+                    },
+                ),
+                prepare(4),
+                freq(5, 2000, 20, None),
+                operation_cmd(
+                    6,
+                    OperationSub::Generate {
+                        mode: StepMode::Continuous,
+                        slot: Some(0),
+                        operation_id: None,
+                    },
+                ),
+                operation_cmd(7, OperationSub::Info),
+            ],
+        );
+
+        // The slot played, the operation prepared meanwhile is still there
+        assert!(bus.outputs().contains(&on(FTW_1KHZ, 0)));
+        assert_eq!(
+            responses(&sent).last(),
+            Some(&"AT+OPERATION=7#INFO#4#1#1#20")
+        );
+    }
+
+    #[test]
+    fn info_and_steps_read_back_the_prepared_operation() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let operation_cmd = |id, sub| Msg::OperationCmd { id, sub };
+        let steps = |id, start, count| operation_cmd(id, OperationSub::Steps { start, count });
+        let sent = drive(
+            &bus,
+            [
+                prepare_repeat(1, 3),
+                operation_cmd(2, OperationSub::Info),
+                freq(3, 1000, 10, None),
+                freq(4, 2000, 20, Some(8)),
+                freq(5, 1000, 30, None),
+                operation_cmd(6, OperationSub::Info),
+                steps(7, 1, 1),
+                steps(8, 2, 16),
+                steps(9, 3, 16),
+            ],
+        );
+
+        assert!(bus.events().is_empty());
+        assert_eq!(responses(&sent)[1..2], ["AT+OPERATION=2#INFO#1#0#3#0"]);
+        assert_eq!(
+            responses(&sent)[5..],
+            [
+                "AT+OPERATION=6#INFO#1#3#3#60",
+                "AT+OPERATION=7#STEP#1#4#2000#20",
+                "AT+OPERATION=7#STEPS#1#1#3#COMPLETED",
+                "AT+OPERATION=8#STEP#2#5#1000#30",
+                "AT+OPERATION=8#STEPS#2#1#3#COMPLETED",
+                "AT+OPERATION=9#STEPS#3#0#3#COMPLETED",
+            ]
+        );
+    }
+
+    #[test]
+    fn step_edits_report_the_index_and_the_step_count() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let operation_cmd = |id, sub| Msg::OperationCmd { id, sub };
+        let params = |hz, time_ms| StepParams {
+            freq: MilliHertz::from_hz(hz),
+            time_ms,
+            phase: None,
+            glide: None,
+            colour: None,
+        };
+        let sent = drive(
+            &bus,
+            [
+                prepare(1),
+                freq(2, 1000, 10, None),
+                freq(3, 1000, 20, None),
+                freq(4, 1000, 30, None),
+                operation_cmd(
+                    5,
+                    OperationSub::Replace {
+                        target: StepRef::Id(3),
+                        step: params(2000, 25),
+                    },
+                ),
+                operation_cmd(
+                    6,
+                    OperationSub::Insert {
+                        target: StepRef::Index(0),
+                        step: params(500, 5),
+                    },
+                ),
+                operation_cmd(
+                    7,
+                    OperationSub::Delete {
+                        target: StepRef::Id(4),
+                    },
+                ),
+                operation_cmd(
+                    8,
+                    OperationSub::Delete {
+                        target: StepRef::Id(4),
+                    },
+                ),
+                operation_cmd(
+                    9,
+                    OperationSub::Replace {
+                        target: StepRef::Index(0),
+                        step: params(CLK_HZ / 2 + 1, 5),
+                    },
+                ),
+                operation_cmd(
+                    10,
+                    OperationSub::Insert {
+                        target: StepRef::Index(4),
+                        step: params(500, 5),
+                    },
+                ),
+                operation_cmd(11, OperationSub::Truncate { len: 2 }),
+                operation_cmd(
+                    12,
+                    OperationSub::Steps {
+                        start: 0,
+                        count: 16,
+                    },
+                ),
+            ],
+        );
+
+        assert!(bus.events().is_empty());
+        assert_eq!(
+            responses(&sent)[4..],
+            [
+                "AT+OPERATION=5#REPLACE#1#3#COMPLETED",
+                "AT+OPERATION=6#INSERT#0#4#COMPLETED",
+                "AT+OPERATION=7#DELETE#3#3#COMPLETED",
+                "AT+OPERATION=11#TRUNCATE#2#COMPLETED",
+                // The inserted step takes the id of its command, a replaced one keeps its own
+                "AT+OPERATION=12#STEP#0#6#500#5",
+                "AT+OPERATION=12#STEP#1#2#1000#10",
+                "AT+OPERATION=12#STEPS#0#2#2#COMPLETED",
+            ]
+        );
+        let errors: Vec<_> = sent
+            .iter()
+            .filter_map(|msg| match msg {
+                Msg::Err(id, e) => Some((*id, e.error_code())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(errors, [(8, 29), (9, 21), (10, 29)]);
+    }
+
+    #[test]
+    fn validate_reports_the_session_without_driving_the_dds() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let validate = |id, operation_id| Msg::OperationCmd {
+            id,
+            sub: OperationSub::Validate { operation_id },
+        };
+        let sent = drive(
+            &bus,
+            [
+                prepare_repeat(1, 0),
+                validate(2, None),
+                freq(3, 1000, 10, None),
+                freq(4, 2000, 20, None),
+                validate(5, None),
+                validate(6, Some(1)),
+                validate(7, Some(9)),
+                prepare_repeat(8, 2),
+                freq(9, 1000, 10, None),
+                freq(10, 2000, 20, None),
+                validate(11, None),
+                freq(12, 1000, 0, None),
+                validate(13, None),
+            ],
+        );
+
+        assert!(bus.events().is_empty());
+        let reports: Vec<_> = responses(&sent)
+            .into_iter()
+            .filter(|line| line.contains("#VALIDATE#"))
+            .collect();
+        assert_eq!(
+            reports,
+            [
+                "AT+OPERATION=2#VALIDATE#FAILED#32",
+                "AT+OPERATION=5#VALIDATE#1#2#0#30#INFINITE#COMPLETED",
+                "AT+OPERATION=6#VALIDATE#1#2#0#30#INFINITE#COMPLETED",
+                "AT+OPERATION=7#VALIDATE#FAILED#33",
+                "AT+OPERATION=11#VALIDATE#8#2#2#30#60#COMPLETED",
+                "AT+OPERATION=13#VALIDATE#FAILED#34#2",
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_runs_validate_first() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = drive(&bus, [prepare(1), generate(2, StepMode::Continuous)]);
+        assert!(matches!(
+            sent.last(),
+            Some(Msg::Err(2, FirmwareError::OperationEmpty))
+        ));
+
+        let sent = drive(
+            &bus,
+            [
+                freq(3, 1000, 10, None),
+                freq(4, 1000, 0, None),
+                generate(5, StepMode::Continuous),
+            ],
+        );
+        assert!(matches!(
+            sent.last(),
+            Some(Msg::Err(5, FirmwareError::ZeroDwell))
+        ));
+        assert!(bus.events().is_empty());
+    }
+
+    fn glide_to(id: u32, hz: u32, time_ms: u32, glide_ms: u32, curve: SweepCurve) -> Msg {
+        Msg::FreqSet {
+            id,
+            freq: MilliHertz::from_hz(hz),
+            time_ms,
+            phase: None,
+            glide: Some(Glide {
+                time_ms: glide_ms,
+                curve,
+            }),
+            colour: None,
+        }
+    }
+
+    /// Outputs of the glide steps of a continuous GENERATE, one per `hz`.
+    fn glide_outputs(hz: &[u32]) -> Vec<MockOutput> {
+        let reference = MockBus::new();
+        let reference = chip(&reference);
+        hz.iter()
+            .map(|&hz| on(reference.freq_to_ftw(MilliHertz::from_hz(hz)), 0))
+            .collect()
+    }
+
+    #[test]
+    fn glide_longer_than_its_step_takes_the_whole_step() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let begin = Instant::now();
+        drive(
+            &bus,
+            [
+                prepare(1),
+                freq(2, 1000, 100, None),
+                // Only a FREQ sent past the parser can glide for longer than its step
+                glide_to(3, 2000, 20, 1000, SweepCurve::Linear),
+                freq(4, 1000, 100, None),
+                generate(5, StepMode::Continuous),
+            ],
+        );
+
+        // Four updates in the 20 ms, the step frequency loaded again at the end
+        let mut expected = std::vec![MockOutput::Reset, on(0, 0)];
+        expected.extend(glide_outputs(&[1000, 1250, 1500, 1750, 2000, 2000, 1000]));
+        expected.push(MockOutput::Off);
+        assert_eq!(bus.outputs().as_slice(), expected);
+        // The next step started on schedule rather than a second later
+        assert!(Instant::now() - begin < Duration::from_millis(900));
+    }
+
+    #[test]
+    fn exponential_glide_through_0hz_goes_linear() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        drive(
+            &bus,
+            [
+                prepare(1),
+                // Nothing plays before the first step, so it does not glide
+                glide_to(2, 0, 10, 10, SweepCurve::Logarithmic),
+                glide_to(3, 1000, 20, 20, SweepCurve::Logarithmic),
+                glide_to(4, 4000, 10, 10, SweepCurve::Logarithmic),
+                glide_to(5, 0, 20, 20, SweepCurve::Logarithmic),
+                generate(6, StepMode::Continuous),
+            ],
+        );
+
+        let mut expected = std::vec![MockOutput::Reset, on(0, 0)];
+        expected.extend(glide_outputs(&[
+            0, // step 2
+            250, 500, 750, 1000, 1000, // step 3, linear out of 0 Hz
+            2000, 4000, 4000, // step 4, exponential
+            3000, 2000, 1000, 0, 0, // step 5, linear into 0 Hz
+        ]));
+        expected.push(MockOutput::Off);
+        assert_eq!(bus.outputs().as_slice(), expected);
+    }
+
+    #[test]
+    fn step_colours_are_cued_and_the_host_colour_restored() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let coloured = |id, hz, colour| Msg::FreqSet {
+            id,
+            freq: MilliHertz::from_hz(hz),
+            time_ms: 10,
+            phase: None,
+            glide: None,
+            colour,
+        };
+        let colours = |sent: &[Msg]| -> Vec<(u8, u8, u8)> {
+            sent.iter()
+                .filter_map(|msg| match *msg {
+                    Msg::RgbSet { id: None, r, g, b } => Some((r, g, b)),
+                    _ => None,
+                })
+                .collect()
+        };
+        crate::rgb::set_host_rgb(Rgb { r: 1, g: 2, b: 3 });
+
+        let sent = drive(
+            &bus,
+            [
+                prepare(1),
+                coloured(2, 1000, Some(Rgb { r: 255, g: 0, b: 0 })),
+                coloured(3, 2000, None),
+                coloured(4, 1000, Some(Rgb { r: 0, g: 0, b: 255 })),
+                generate(5, StepMode::Continuous),
+            ],
+        );
+        // A step without a colour keeps the one before it
+        assert_eq!(colours(&sent), [(255, 0, 0), (0, 0, 255), (1, 2, 3)]);
+
+        // Without step colours the LED is left to SETRGB
+        let sent = drive(
+            &bus,
+            [
+                prepare(6),
+                freq(7, 1000, 10, None),
+                generate(8, StepMode::Continuous),
+            ],
+        );
+        assert!(colours(&sent).is_empty());
+        crate::rgb::set_host_rgb(Rgb::default());
+    }
+
+    #[test]
+    fn phase_command_sets_the_phase_of_steps_without_one() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = drive(
+            &bus,
+            [
+                Msg::PhaseSet { id: 1, phase: 4 },
+                prepare(2),
+                freq(3, 1000, 5, None),
+                freq(4, 1000, 5, Some(8)),
+                generate(5, StepMode::Continuous),
+            ],
+        );
+
+        assert_eq!(responses(&sent)[0], "AT+PHASE=1#4#COMPLETED");
+        assert_eq!(
+            bus.outputs()[2..],
+            [on(FTW_1KHZ, 4), on(FTW_1KHZ, 8), MockOutput::Off]
+        );
+    }
+
+    #[test]
+    fn freqinfo_reports_the_achieved_frequency_and_ppm_error() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let info = |id, freq| Msg::FreqInfo { id, freq };
+        let sent = drive(
+            &bus,
+            [
+                info(1, MilliHertz::from_hz(1000)),
+                info(2, MilliHertz::from_hz(CLK_HZ / 2 + 1)),
+            ],
+        );
+
+        assert!(bus.events().is_empty());
+        assert_eq!(
+            responses(&sent),
+            [format!("AT+FREQINFO=1#1000#{ACHIEVED_1KHZ}#{FTW_1KHZ}").as_str()]
+        );
+        assert!(matches!(
+            sent.last(),
+            Some(Msg::Err(2, FirmwareError::FreqOutOfRange))
+        ));
+    }
+
+    #[test]
+    fn calibrate_applies_and_stores_the_correction() {
+        let _serial = serial();
+        block_on(init_storage(RamFlash::default()));
+        let bus = MockBus::new();
+        let calibrate = |id, sub| Msg::Calibrate { id, sub };
+        let requested = MilliHertz::from_hz(1_000_000);
+        let measured = MilliHertz(999_999_000);
+        let mut dds = chip(&bus);
+        let sent = drive_dds(
+            &mut dds,
+            [
+                calibrate(1, CalibrateSub::Offset { ppb: 1_000_000 }),
+                Msg::CalibrateQuery,
+                // About 1 ppm low against the corrected clock
+                calibrate(
+                    2,
+                    CalibrateSub::Measured {
+                        requested,
+                        measured,
+                    },
+                ),
+                // Off by more than CAL_PPB_LIMIT
+                calibrate(
+                    3,
+                    CalibrateSub::Measured {
+                        requested,
+                        measured: MilliHertz::from_hz(2_000_000),
+                    },
+                ),
+            ],
+        );
+
+        // The measurement is against what the corrected clock achieves
+        let mut expected = chip(&bus);
+        expected.set_clock_ppb(1_000_000);
+        let ppb = calibration_ppb(1_000_000, expected.achieved_freq(requested), measured) as i32;
+        assert!((998_900..999_100).contains(&ppb));
+        assert_eq!(
+            responses(&sent),
+            [
+                "AT+CALIBRATE=1#1000000#COMPLETED",
+                "AT+CALIBRATE=0#1000000",
+                &format!("AT+CALIBRATE=2#{ppb}#COMPLETED")
+            ]
+        );
+        assert!(matches!(
+            sent.last(),
+            Some(Msg::Err(
+                3,
+                FirmwareError::Hexa(hexa_tune_proto_embedded::HexaError::InvalidParam)
+            ))
+        ));
+
+        // Tuning words come from the corrected clock, which is stored
+        assert_eq!(dds.clock_ppb(), ppb);
+        expected.set_clock_ppb(ppb);
+        let tone = MilliHertz::from_hz(1000);
+        assert_eq!(dds.freq_to_ftw(tone), expected.freq_to_ftw(tone));
+        assert_ne!(dds.freq_to_ftw(tone), FTW_1KHZ);
+        assert_eq!(block_on(load_config()).map(|c| c.clock_ppb), Some(ppb));
+    }
+
+    #[test]
+    fn freq_above_nyquist_is_rejected() {
+        let _serial = serial();
+        let bus = MockBus::new();
+        let sent = drive(&bus, [prepare(1), freq(2, CLK_HZ / 2 + 1, 10, None)]);
+
+        assert!(matches!(
+            sent.last(),
+            Some(Msg::Err(2, FirmwareError::FreqOutOfRange))
+        ));
+    }
+}
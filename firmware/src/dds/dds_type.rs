@@ -5,15 +5,226 @@ use heapless::Vec;
 
 use crate::error::FirmwareError;
 
+/// Frequency in millihertz, the resolution of FREQ steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct MilliHertz(pub u64);
+
+impl MilliHertz {
+    #[allow(dead_code)]
+    pub const fn from_hz(hz: u32) -> Self {
+        Self(hz as u64 * 1000)
+    }
+
+    /// Whole hertz, rounded down.
+    pub const fn hz(self) -> u32 {
+        (self.0 / 1000) as u32
+    }
+
+    /// Fractional part below one hertz, in millihertz.
+    pub const fn frac_millihertz(self) -> u32 {
+        (self.0 % 1000) as u32
+    }
+
+    /// Deviation of `self` from `requested` in ppm, rounded to the nearest integer.
+    pub fn ppm_from(self, requested: MilliHertz) -> i32 {
+        if requested.0 == 0 {
+            return 0;
+        }
+        let diff = (self.0 as i128 - requested.0 as i128) * 1_000_000;
+        let den = requested.0 as i128;
+        let ppm = if diff >= 0 {
+            (diff + den / 2) / den
+        } else {
+            (diff - den / 2) / den
+        };
+        ppm.clamp(i32::MIN as i128, i32::MAX as i128) as i32
+    }
+}
+
+impl defmt::Format for MilliHertz {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}mHz", self.0);
+    }
+}
+
+/// Largest accepted reference clock correction, in ppb (+-1000 ppm).
+pub const CAL_PPB_LIMIT: i32 = 1_000_000;
+
+/// Clock in millihertz after applying a `ppb` correction to a nominal clock in Hz.
+pub const fn calibrated_clk(nominal_hz: u32, ppb: i32) -> MilliHertz {
+    let nominal = nominal_hz as i64 * 1000;
+    let delta = nominal_hz as i64 * ppb as i64;
+    // nominal_hz * ppb / 1e6 is the correction in mHz, rounded half away from zero
+    let delta = if delta >= 0 {
+        (delta + 500_000) / 1_000_000
+    } else {
+        (delta - 500_000) / 1_000_000
+    };
+    MilliHertz((nominal + delta) as u64)
+}
+
+/// Correction in ppb that makes `achieved` (computed with `current_ppb`) match
+/// the `measured` output frequency.
+pub fn calibration_ppb(current_ppb: i32, achieved: MilliHertz, measured: MilliHertz) -> i64 {
+    if achieved.0 == 0 {
+        return current_ppb as i64;
+    }
+    // The tuning word is fixed, so the true clock scales with the measured output
+    let scaled = (1_000_000_000 + current_ppb as i128) * measured.0 as i128;
+    let den = achieved.0 as i128;
+    let ppb = (scaled + den / 2) / den - 1_000_000_000;
+    ppb.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+/// Number of phase offset steps of the AD985x, 11.25 degrees each.
+pub const PHASE_STEPS: u8 = 32;
+
+/// How GENERATE moves from one step to the next.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum StepMode {
+    /// Only the tuning word changes; the phase accumulator keeps running
+    Continuous,
+    /// The chip is powered down and reset around every step
+    Reset,
+}
+
+/// Output waveform of the DDS.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    Square,
+}
+
+impl Waveform {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            b"SINE" => Some(Waveform::Sine),
+            b"TRIANGLE" => Some(Waveform::Triangle),
+            b"SQUARE" => Some(Waveform::Square),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Waveform::Sine => b"SINE",
+            Waveform::Triangle => b"TRIANGLE",
+            Waveform::Square => b"SQUARE",
+        }
+    }
+}
+
+/// Frequency curve of a sweep step.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SweepCurve {
+    Linear,
+    Logarithmic,
+}
+
+impl SweepCurve {
+    /// Frequency a fraction `t` of the way from `start` to `end`.
+    fn at(self, start: MilliHertz, end: MilliHertz, t: f64) -> MilliHertz {
+        let (start, end) = (start.0 as f64, end.0 as f64);
+        let freq = match self {
+            SweepCurve::Linear => start + (end - start) * t,
+            SweepCurve::Logarithmic => start * libm::pow(end / start, t),
+        };
+        MilliHertz(libm::round(freq) as u64)
+    }
+}
+
+/// A sweep from the step frequency to `end`, updated every `update_ms`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Sweep {
+    pub end: MilliHertz,
+    pub curve: SweepCurve,
+    pub update_ms: u32,
+}
+
+impl Sweep {
+    /// Number of tuning word updates for a sweep lasting `time_ms`, at least
+    /// two so that both ends are loaded.
+    pub fn updates(&self, time_ms: u32) -> u32 {
+        (time_ms / self.update_ms.max(1)).max(2)
+    }
+
+    /// Frequency of update `k` out of `updates`; the first update loads
+    /// `start` and the last, `k == updates - 1`, loads `end`.
+    pub fn freq_at(&self, start: MilliHertz, k: u32, updates: u32) -> MilliHertz {
+        let t = k as f64 / updates.saturating_sub(1).max(1) as f64;
+        self.curve.at(start, self.end, t)
+    }
+}
+
+/// Tuning word update interval of a glide.
+pub const GLIDE_UPDATE_MS: u32 = 5;
+
+/// A ramp into a step from the frequency the previous step left the output at.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Glide {
+    /// Part of the step time spent ramping
+    pub time_ms: u32,
+    /// `Logarithmic` is the exponential glide, even steps in pitch
+    pub curve: SweepCurve,
+}
+
+impl Glide {
+    /// Number of tuning word updates for a glide lasting `time_ms`.
+    pub fn updates(time_ms: u32) -> u32 {
+        (time_ms / GLIDE_UPDATE_MS).max(1)
+    }
+
+    /// Frequency of update `k` out of `updates` on the way from `from`, which
+    /// the output already holds, to `to`; the last update loads `to`. An
+    /// exponential glide has no path through 0 Hz and falls back to linear there.
+    pub fn freq_at(&self, from: MilliHertz, to: MilliHertz, k: u32, updates: u32) -> MilliHertz {
+        let curve = match from.0 == 0 || to.0 == 0 {
+            true => SweepCurve::Linear,
+            false => self.curve,
+        };
+        curve.at(from, to, (k + 1) as f64 / updates.max(1) as f64)
+    }
+}
+
+/// Colour of the RGB LED.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct FreqStep {
     pub id: u32,
-    pub freq: u32,
+    pub freq: MilliHertz,
     pub time_ms: u32,
+    /// Phase offset in 11.25 degree steps, `None` keeps the default phase
+    pub phase: Option<u8>,
+    /// Sweep from `freq` over `time_ms` instead of holding a fixed frequency
+    pub sweep: Option<Sweep>,
+    /// Ramp in from the previous step over the start of `time_ms`
+    pub glide: Option<Glide>,
+    /// Colour the RGB LED shows from the start of the step
+    pub colour: Option<Rgb>,
 }
 
+/// Most steps an operation can hold.
+pub const OPERATION_STEPS: usize = 64;
+
+/// Longest GENERATE session in ms (about 34 years), keeping every step
+/// deadline well inside the timer range.
+pub const SESSION_MAX_MS: u64 = 1 << 40;
+
+#[derive(Clone)]
 pub struct Operation {
     id: u32,
-    steps: Vec<FreqStep, 64>,
+    steps: Vec<FreqStep, OPERATION_STEPS>,
+    /// Passes over the steps per GENERATE, 0 repeats until stopped
+    repeat: u32,
+    /// How late each step of the last pass of GENERATE started against its schedule, in us
+    timing_us: Vec<i32, OPERATION_STEPS>,
 }
 
 impl Operation {
@@ -21,10 +232,11 @@ impl Operation {
         Self {
             id: 0,
             steps: Vec::new(),
+            repeat: 1,
+            timing_us: Vec::new(),
         }
     }
 
-    #[allow(dead_code)]
     pub fn get_id(&self) -> u32 {
         self.id
     }
@@ -33,13 +245,465 @@ impl Operation {
         self.id = id;
     }
 
-    pub fn get_steps(&self) -> &Vec<FreqStep, 64> {
+    pub fn get_repeat(&self) -> u32 {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: u32) {
+        self.repeat = repeat;
+    }
+
+    pub fn get_steps(&self) -> &Vec<FreqStep, OPERATION_STEPS> {
         &self.steps
     }
 
+    /// Length of one pass over the steps, in ms.
+    pub fn duration_ms(&self) -> u64 {
+        self.steps.iter().map(|step| step.time_ms as u64).sum()
+    }
+
+    /// Length of the whole GENERATE in ms, `None` when it repeats until stopped.
+    pub fn session_ms(&self) -> Option<u64> {
+        match self.repeat {
+            0 => None,
+            repeat => Some(self.duration_ms().saturating_mul(repeat as u64)),
+        }
+    }
+
+    /// Check that GENERATE can play the operation on a DDS that tops out at
+    /// `max_freq`. The error carries the index of the offending step, if any.
+    pub fn validate(&self, max_freq: MilliHertz) -> Result<(), (FirmwareError, Option<usize>)> {
+        if self.steps.is_empty() {
+            return Err((FirmwareError::OperationEmpty, None));
+        }
+        for (index, step) in self.steps.iter().enumerate() {
+            let end = step.sweep.map_or(step.freq, |sweep| sweep.end);
+            if step.freq > max_freq || end > max_freq {
+                return Err((FirmwareError::FreqOutOfRange, Some(index)));
+            }
+            if step.time_ms == 0 {
+                return Err((FirmwareError::ZeroDwell, Some(index)));
+            }
+        }
+        // A looping operation only schedules one pass ahead
+        let scheduled = self.session_ms().unwrap_or(self.duration_ms());
+        if scheduled > SESSION_MAX_MS {
+            return Err((FirmwareError::SessionTooLong, None));
+        }
+        Ok(())
+    }
+
     pub fn add_step(&mut self, step: FreqStep) -> Result<(), FirmwareError> {
         self.steps
             .push(step)
             .map_err(|_| FirmwareError::OperationStepsFull)
     }
+
+    /// Index of the step with id `step_id`.
+    pub fn find_step(&self, step_id: u32) -> Option<usize> {
+        self.steps.iter().position(|step| step.id == step_id)
+    }
+
+    pub fn replace_step(&mut self, index: usize, step: FreqStep) -> Result<(), FirmwareError> {
+        let slot = self
+            .steps
+            .get_mut(index)
+            .ok_or(FirmwareError::StepNotFound)?;
+        *slot = step;
+        self.timing_us.clear();
+        Ok(())
+    }
+
+    /// Insert `step` before `index`; `index` may be the step count to append.
+    pub fn insert_step(&mut self, index: usize, step: FreqStep) -> Result<(), FirmwareError> {
+        if index > self.steps.len() {
+            return Err(FirmwareError::StepNotFound);
+        }
+        self.steps
+            .insert(index, step)
+            .map_err(|_| FirmwareError::OperationStepsFull)?;
+        self.timing_us.clear();
+        Ok(())
+    }
+
+    pub fn remove_step(&mut self, index: usize) -> Result<FreqStep, FirmwareError> {
+        if index >= self.steps.len() {
+            return Err(FirmwareError::StepNotFound);
+        }
+        self.timing_us.clear();
+        Ok(self.steps.remove(index))
+    }
+
+    /// Keep only the first `len` steps.
+    pub fn truncate(&mut self, len: usize) {
+        self.steps.truncate(len);
+        self.timing_us.clear();
+    }
+
+    pub fn get_timing(&self) -> &Vec<i32, OPERATION_STEPS> {
+        &self.timing_us
+    }
+
+    pub fn set_timing(&mut self, timing_us: Vec<i32, OPERATION_STEPS>) {
+        self.timing_us = timing_us;
+    }
+}
+
+impl Default for Operation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(end_hz: u32, curve: SweepCurve) -> Sweep {
+        Sweep {
+            end: MilliHertz::from_hz(end_hz),
+            curve,
+            update_ms: 10,
+        }
+    }
+
+    fn path(sweep: &Sweep, start_hz: u32, updates: u32) -> std::vec::Vec<u64> {
+        (0..updates)
+            .map(|k| sweep.freq_at(MilliHertz::from_hz(start_hz), k, updates).0)
+            .collect()
+    }
+
+    #[test]
+    fn calibrated_clock_moves_by_ppb() {
+        assert_eq!(calibrated_clk(125_000_000, 0), MilliHertz(125_000_000_000));
+        // 1 ppb of 125 MHz is 125 mHz
+        assert_eq!(calibrated_clk(125_000_000, 1), MilliHertz(125_000_000_125));
+        assert_eq!(calibrated_clk(125_000_000, -1), MilliHertz(124_999_999_875));
+        // Half a millihertz rounds away from zero
+        assert_eq!(calibrated_clk(500_000, 1), MilliHertz(500_000_001));
+        assert_eq!(calibrated_clk(500_000, -1), MilliHertz(499_999_999));
+    }
+
+    #[test]
+    fn calibrated_clock_does_not_overflow_at_the_limit() {
+        // +-1000 ppm on the largest clock
+        assert_eq!(
+            calibrated_clk(u32::MAX, CAL_PPB_LIMIT),
+            MilliHertz(u32::MAX as u64 * 1001)
+        );
+        assert_eq!(
+            calibrated_clk(u32::MAX, -CAL_PPB_LIMIT),
+            MilliHertz(u32::MAX as u64 * 999)
+        );
+    }
+
+    #[test]
+    fn measured_output_gives_the_clock_correction() {
+        let achieved = MilliHertz::from_hz(1_000_000);
+        // An output 1 ppm high means the clock runs 1 ppm fast
+        assert_eq!(
+            calibration_ppb(0, achieved, MilliHertz(1_000_001_000)),
+            1000
+        );
+        assert_eq!(calibration_ppb(0, achieved, MilliHertz(999_999_000)), -1000);
+        assert_eq!(
+            calibration_ppb(0, achieved, MilliHertz(1_001_000_000)),
+            CAL_PPB_LIMIT as i64
+        );
+        // On top of the correction the achieved frequency was computed with
+        assert_eq!(calibration_ppb(500, achieved, achieved), 500);
+        assert_eq!(
+            calibration_ppb(500, achieved, MilliHertz(1_000_001_000)),
+            1500
+        );
+
+        // Way out of range, but reported as such rather than wrapped
+        assert_eq!(
+            calibration_ppb(i32::MAX, MilliHertz(1), MilliHertz(u64::MAX)),
+            i64::MAX
+        );
+        assert_eq!(
+            calibration_ppb(0, MilliHertz(u64::MAX), MilliHertz(1)),
+            -1_000_000_000
+        );
+    }
+
+    #[test]
+    fn ppm_error_rounds_half_away_from_zero() {
+        let requested = MilliHertz::from_hz(1000);
+        assert_eq!(MilliHertz(1_000_008).ppm_from(requested), 8);
+        assert_eq!(MilliHertz(999_961).ppm_from(requested), -39);
+
+        // 0.5 ppm either way
+        let requested = MilliHertz::from_hz(2000);
+        assert_eq!(MilliHertz(2_000_001).ppm_from(requested), 1);
+        assert_eq!(MilliHertz(1_999_999).ppm_from(requested), -1);
+
+        assert_eq!(MilliHertz(1).ppm_from(MilliHertz(0)), 0);
+    }
+
+    #[test]
+    fn linear_sweep_starts_on_start_and_lands_on_end() {
+        let sweep = sweep(2000, SweepCurve::Linear);
+        assert_eq!(
+            path(&sweep, 1000, 5),
+            [1_000_000, 1_250_000, 1_500_000, 1_750_000, 2_000_000]
+        );
+    }
+
+    #[test]
+    fn logarithmic_sweep_starts_on_start_and_lands_on_end() {
+        let sweep = sweep(1600, SweepCurve::Logarithmic);
+        assert_eq!(
+            path(&sweep, 100, 5),
+            [100_000, 200_000, 400_000, 800_000, 1_600_000]
+        );
+    }
+
+    #[test]
+    fn first_update_loads_start() {
+        // 1 Hz to 10 kHz in 10 updates used to open at about 1 kHz
+        for curve in [SweepCurve::Linear, SweepCurve::Logarithmic] {
+            let sweep = sweep(10_000, curve);
+            let updates = sweep.updates(100);
+            assert_eq!(updates, 10);
+            assert_eq!(path(&sweep, 1, updates)[0], 1_000);
+        }
+    }
+
+    #[test]
+    fn short_sweep_loads_both_ends() {
+        for curve in [SweepCurve::Linear, SweepCurve::Logarithmic] {
+            let sweep = sweep(3000, curve);
+            assert_eq!(sweep.updates(5), 2);
+            assert_eq!(path(&sweep, 1000, 2), [1_000_000, 3_000_000]);
+        }
+    }
+
+    #[test]
+    fn downward_sweep_lands_on_end() {
+        let sweep = sweep(1000, SweepCurve::Linear);
+        assert_eq!(path(&sweep, 2000, 3), [2_000_000, 1_500_000, 1_000_000]);
+    }
+
+    fn glide_path(glide: &Glide, from_hz: u32, to_hz: u32) -> std::vec::Vec<u64> {
+        let updates = Glide::updates(glide.time_ms);
+        let (from, to) = (MilliHertz::from_hz(from_hz), MilliHertz::from_hz(to_hz));
+        (0..updates)
+            .map(|k| glide.freq_at(from, to, k, updates).0)
+            .collect()
+    }
+
+    #[test]
+    fn glide_leaves_from_and_lands_on_target() {
+        let glide = Glide {
+            time_ms: 20,
+            curve: SweepCurve::Logarithmic,
+        };
+        // The previous step already holds 440 Hz, so the glide moves off it at once
+        assert_eq!(
+            glide_path(&glide, 440, 7040),
+            [880_000, 1_760_000, 3_520_000, 7_040_000]
+        );
+
+        // No exponential path out of 0 Hz, the glide goes linear
+        assert_eq!(
+            glide_path(&glide, 0, 1000),
+            [250_000, 500_000, 750_000, 1_000_000]
+        );
+    }
+
+    fn step(id: u32) -> FreqStep {
+        FreqStep {
+            id,
+            freq: MilliHertz::from_hz(1000),
+            time_ms: 10,
+            phase: None,
+            sweep: None,
+            glide: None,
+            colour: None,
+        }
+    }
+
+    fn operation(ids: impl IntoIterator<Item = u32>) -> Operation {
+        let mut operation = Operation::new();
+        for id in ids {
+            operation.add_step(step(id)).ok().unwrap();
+        }
+        operation
+    }
+
+    fn ids(operation: &Operation) -> std::vec::Vec<u32> {
+        operation.get_steps().iter().map(|step| step.id).collect()
+    }
+
+    #[derive(Clone, Copy)]
+    enum Edit {
+        Replace(usize),
+        Insert(usize),
+        Remove(usize),
+        Truncate(usize),
+    }
+
+    #[test]
+    fn step_edits_check_the_index() {
+        // On steps 1, 2, 3, the new step being 9; errors as their code
+        let cases: [(Edit, Result<&[u32], u8>); 10] = [
+            (Edit::Replace(0), Ok(&[9, 2, 3])),
+            (Edit::Replace(2), Ok(&[1, 2, 9])),
+            (Edit::Replace(3), Err(29)),
+            (Edit::Insert(0), Ok(&[9, 1, 2, 3])),
+            (Edit::Insert(3), Ok(&[1, 2, 3, 9])),
+            (Edit::Insert(4), Err(29)),
+            (Edit::Remove(1), Ok(&[1, 3])),
+            (Edit::Remove(3), Err(29)),
+            (Edit::Truncate(2), Ok(&[1, 2])),
+            (Edit::Truncate(5), Ok(&[1, 2, 3])),
+        ];
+        for (i, (edit, expected)) in cases.into_iter().enumerate() {
+            let mut operation = operation(1..=3);
+            let result = match edit {
+                Edit::Replace(index) => operation.replace_step(index, step(9)),
+                Edit::Insert(index) => operation.insert_step(index, step(9)),
+                Edit::Remove(index) => operation.remove_step(index).map(|_| ()),
+                Edit::Truncate(len) => {
+                    operation.truncate(len);
+                    Ok(())
+                }
+            };
+            match expected {
+                Ok(expected) => {
+                    assert!(result.is_ok(), "case {}", i);
+                    assert_eq!(ids(&operation), expected, "case {}", i);
+                }
+                Err(code) => {
+                    assert_eq!(
+                        result.err().map(|e| e.error_code()),
+                        Some(code),
+                        "case {}",
+                        i
+                    );
+                    // A refused edit leaves the steps alone
+                    assert_eq!(ids(&operation), [1, 2, 3], "case {}", i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn full_operation_refuses_another_step() {
+        let mut operation = operation(0..OPERATION_STEPS as u32);
+        let full = |result: Result<(), FirmwareError>| result.err().map(|e| e.error_code());
+        assert_eq!(full(operation.add_step(step(99))), Some(20));
+        assert_eq!(full(operation.insert_step(0, step(99))), Some(20));
+        assert_eq!(ids(&operation)[0], 0);
+        assert_eq!(operation.get_steps().len(), OPERATION_STEPS);
+
+        // Replacing a step keeps the count
+        operation
+            .replace_step(OPERATION_STEPS - 1, step(99))
+            .ok()
+            .unwrap();
+        assert_eq!(operation.find_step(99), Some(OPERATION_STEPS - 1));
+        operation.remove_step(0).ok().unwrap();
+        assert!(operation.insert_step(0, step(100)).is_ok());
+    }
+
+    #[test]
+    fn step_edits_drop_the_stale_timing() {
+        let edits: [fn(&mut Operation); 4] = [
+            |operation| operation.replace_step(0, step(9)).ok().unwrap(),
+            |operation| operation.insert_step(0, step(9)).ok().unwrap(),
+            |operation| {
+                operation.remove_step(0).ok().unwrap();
+            },
+            |operation| operation.truncate(1),
+        ];
+        for edit in edits {
+            let mut operation = operation(1..=2);
+            operation.set_timing(Vec::from_slice(&[5, -5]).unwrap());
+            edit(&mut operation);
+            assert!(operation.get_timing().is_empty());
+        }
+    }
+
+    #[test]
+    fn find_step_gives_the_first_index_with_the_id() {
+        let operation = operation([4, 7, 7]);
+        assert_eq!(operation.find_step(7), Some(1));
+        assert_eq!(operation.find_step(5), None);
+    }
+
+    #[test]
+    fn validate_names_the_problem_and_the_step() {
+        let max = MilliHertz::from_hz(1000);
+        let with = |edit: fn(&mut FreqStep)| {
+            let mut operation = operation(1..=3);
+            let mut step = operation.get_steps()[1];
+            edit(&mut step);
+            operation.replace_step(1, step).ok().unwrap();
+            operation
+        };
+        // Error code and step index
+        type Verdict = Result<(), (u8, Option<usize>)>;
+        let cases: [(Operation, Verdict); 6] = [
+            (operation(1..=3), Ok(())),
+            (Operation::new(), Err((32, None))),
+            (
+                with(|step| step.freq = MilliHertz(1_000_001)),
+                Err((21, Some(1))),
+            ),
+            (
+                with(|step| {
+                    step.sweep = Some(Sweep {
+                        end: MilliHertz(1_000_001),
+                        curve: SweepCurve::Linear,
+                        update_ms: 10,
+                    })
+                }),
+                Err((21, Some(1))),
+            ),
+            (with(|step| step.time_ms = 0), Err((34, Some(1)))),
+            // Right at the limit is still playable
+            (with(|step| step.freq = MilliHertz(1_000_000)), Ok(())),
+        ];
+        for (i, (operation, expected)) in cases.into_iter().enumerate() {
+            let result = operation
+                .validate(max)
+                .map_err(|(e, index)| (e.error_code(), index));
+            assert_eq!(result, expected, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn validate_bounds_the_session_length() {
+        let mut operation = Operation::new();
+        operation
+            .add_step(FreqStep {
+                time_ms: u32::MAX,
+                ..step(1)
+            })
+            .ok()
+            .unwrap();
+        let max = MilliHertz::from_hz(1000);
+        let repeats = (SESSION_MAX_MS / u32::MAX as u64) as u32;
+        operation.set_repeat(repeats);
+        assert!(operation.validate(max).is_ok());
+        operation.set_repeat(repeats + 1);
+        assert_eq!(
+            operation.session_ms(),
+            Some(u32::MAX as u64 * (repeats as u64 + 1))
+        );
+        assert_eq!(
+            operation
+                .validate(max)
+                .err()
+                .map(|(e, index)| (e.error_code(), index)),
+            Some((35, None))
+        );
+        // Looping schedules one pass at a time, so any pass length will do
+        operation.set_repeat(0);
+        assert_eq!(operation.session_ms(), None);
+        assert!(operation.validate(max).is_ok());
+    }
 }
@@ -0,0 +1,332 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+//! Recording bus for running the DDS drivers off the board.
+//!
+//! `MockBus` hands out `MockPin`s that replace the AD985x W_CLK, FQ_UD, DATA
+//! and RESET GPIOs, and a `MockSpi` with its FSYNC pin for the AD983x. On the
+//! AD985x lines, bits are sampled on W_CLK rising edges and every FQ_UD strobe
+//! is logged together with the bits shifted in since the last one. On the
+//! AD983x, every 16-bit word framed by FSYNC is logged. A test can assert the
+//! exact words the chip would have latched, or compare `outputs()`, which
+//! reads the same for both chips.
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use embedded_hal::digital::{ErrorType, OutputPin};
+use embedded_hal::spi::{self, SpiBus};
+use heapless::Vec;
+
+#[cfg(feature = "ad983x")]
+use crate::dds::Ad983x;
+#[cfg(not(feature = "ad983x"))]
+use crate::dds::{Ad985x, DdsVariant, GpioLink};
+
+pub const MOCK_EVENTS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockEvent {
+    /// RESET line pulsed high.
+    Reset,
+    /// FQ_UD strobe after a full 40-bit word (tuning word + control byte).
+    Word { ftw: u32, ctrl: u8 },
+    /// FQ_UD strobe after any other number of W_CLK pulses.
+    Strobe { bits: u8 },
+    /// AD983x word clocked in between FSYNC falling and rising.
+    Spi(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockLine {
+    Wclk,
+    FqUd,
+    Data,
+    Rst,
+    Fsync,
+}
+
+/// What the chip puts out after a latch, the same for the AD985x and AD983x.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockOutput {
+    /// Chip reset.
+    Reset,
+    /// Output powered down.
+    Off,
+    /// Output running on tuning word `ftw`, `phase` in 11.25 degree steps.
+    On { ftw: u32, phase: u8 },
+}
+
+// AD985x control byte and AD983x register bits, as the datasheets lay them out
+const AD985X_PWRDOWN: u8 = 1 << 2;
+const AD985X_PHASE_SHIFT: u8 = 3;
+const AD983X_B28: u16 = 1 << 13;
+const AD983X_HLB: u16 = 1 << 12;
+const AD983X_FSELECT: u16 = 1 << 11;
+const AD983X_PSELECT: u16 = 1 << 10;
+const AD983X_RESET: u16 = 1 << 8;
+const AD983X_SLEEP1: u16 = 1 << 7;
+
+struct MockState {
+    levels: [bool; 5],
+    shift: u64,
+    bits: u8,
+    events: Vec<MockEvent, MOCK_EVENTS>,
+}
+
+pub struct MockBus {
+    state: RefCell<MockState>,
+}
+
+impl MockBus {
+    pub const fn new() -> Self {
+        Self {
+            state: RefCell::new(MockState {
+                // FSYNC idles high
+                levels: [false, false, false, false, true],
+                shift: 0,
+                bits: 0,
+                events: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn pin(&self, line: MockLine) -> MockPin<'_> {
+        MockPin { bus: self, line }
+    }
+
+    pub fn spi(&self) -> MockSpi<'_> {
+        MockSpi { bus: self }
+    }
+
+    /// Build an AD985x driver wired to this bus.
+    #[cfg(not(feature = "ad983x"))]
+    pub fn ad985x(&self, variant: DdsVariant, ref_clk_hz: u32) -> Ad985x<GpioLink<MockPin<'_>>> {
+        let link = GpioLink::new(
+            self.pin(MockLine::Wclk),
+            self.pin(MockLine::FqUd),
+            self.pin(MockLine::Data),
+            self.pin(MockLine::Rst),
+        );
+        Ad985x::new(link, variant, ref_clk_hz)
+    }
+
+    /// Build an AD983x driver wired to this bus.
+    #[cfg(feature = "ad983x")]
+    pub fn ad983x(&self, mclk_hz: u32) -> Ad983x<MockSpi<'_>, MockPin<'_>> {
+        Ad983x::new(self.spi(), self.pin(MockLine::Fsync), mclk_hz)
+    }
+
+    pub fn events(&self) -> Vec<MockEvent, MOCK_EVENTS> {
+        self.state.borrow().events.clone()
+    }
+
+    /// Only the full 40-bit words latched by FQ_UD, in order.
+    pub fn words(&self) -> Vec<(u32, u8), MOCK_EVENTS> {
+        self.state
+            .borrow()
+            .events
+            .iter()
+            .filter_map(|e| match *e {
+                MockEvent::Word { ftw, ctrl } => Some((ftw, ctrl)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Only the AD983x words, in order.
+    pub fn spi_words(&self) -> Vec<u16, MOCK_EVENTS> {
+        self.state
+            .borrow()
+            .events
+            .iter()
+            .filter_map(|e| match *e {
+                MockEvent::Spi(word) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The output after every latch, decoded from the logged words.
+    ///
+    /// An output the chip is reset out of straight away is left out: powering
+    /// up leaves the AD985x on a zero tuning word and the AD983x on its last
+    /// one, and neither is heard before the reset.
+    pub fn outputs(&self) -> Vec<MockOutput, MOCK_EVENTS> {
+        let mut outputs: Vec<MockOutput, MOCK_EVENTS> = Vec::new();
+        // AD983x FREQ0/FREQ1 and PHASE0/PHASE1, and whether the next FREQ
+        // write of each is its LSBs
+        let mut freq = [0u32; 2];
+        let mut lsb_next = [true; 2];
+        let mut phase = [0u16; 2];
+        for event in self.events() {
+            let output = match event {
+                MockEvent::Reset => MockOutput::Reset,
+                MockEvent::Strobe { .. } => continue,
+                MockEvent::Word { ctrl, .. } if ctrl & AD985X_PWRDOWN != 0 => MockOutput::Off,
+                MockEvent::Word { ftw, ctrl } => MockOutput::On {
+                    ftw,
+                    phase: ctrl >> AD985X_PHASE_SHIFT,
+                },
+                MockEvent::Spi(word) => match word >> 14 {
+                    0 if word & AD983X_RESET != 0 => MockOutput::Reset,
+                    0 if word & AD983X_SLEEP1 != 0 => MockOutput::Off,
+                    0 => {
+                        if word & AD983X_B28 == 0 || word & AD983X_HLB != 0 {
+                            // Separate LSB/MSB writes are all the driver uses
+                            panic!("unexpected AD983x control word {:#06x}", word);
+                        }
+                        let fsel = (word & AD983X_FSELECT != 0) as usize;
+                        let psel = (word & AD983X_PSELECT != 0) as usize;
+                        MockOutput::On {
+                            ftw: freq[fsel],
+                            // 32 phase steps onto the 12-bit register
+                            phase: (phase[psel] >> 7) as u8,
+                        }
+                    }
+                    3 => {
+                        phase[((word >> 13) & 1) as usize] = word & 0x0FFF;
+                        continue;
+                    }
+                    reg => {
+                        let reg = reg as usize - 1;
+                        let bits = (word & 0x3FFF) as u32;
+                        freq[reg] = match lsb_next[reg] {
+                            true => (freq[reg] & !0x3FFF) | bits,
+                            false => (freq[reg] & 0x3FFF) | (bits << 14),
+                        };
+                        lsb_next[reg] = !lsb_next[reg];
+                        continue;
+                    }
+                },
+            };
+            if output == MockOutput::Reset && matches!(outputs.last(), Some(MockOutput::On { .. }))
+            {
+                outputs.pop();
+            }
+            outputs.push(output).ok();
+        }
+        outputs
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.borrow_mut();
+        state.shift = 0;
+        state.bits = 0;
+        state.events.clear();
+    }
+
+    fn drive(&self, line: MockLine, high: bool) {
+        let mut state = self.state.borrow_mut();
+        let rising = high && !state.levels[line as usize];
+        let falling = !high && state.levels[line as usize];
+        state.levels[line as usize] = high;
+        if line == MockLine::Fsync && falling {
+            state.shift = 0;
+            state.bits = 0;
+        }
+        if !rising {
+            return;
+        }
+
+        let event = match line {
+            MockLine::Wclk => {
+                if state.bits < 64 && state.levels[MockLine::Data as usize] {
+                    state.shift |= 1 << state.bits;
+                }
+                state.bits = state.bits.saturating_add(1);
+                return;
+            }
+            MockLine::Data => return,
+            MockLine::FqUd if state.bits == 40 => MockEvent::Word {
+                ftw: state.shift as u32,
+                ctrl: (state.shift >> 32) as u8,
+            },
+            MockLine::FqUd => MockEvent::Strobe { bits: state.bits },
+            MockLine::Rst => MockEvent::Reset,
+            MockLine::Fsync if state.bits == 16 => MockEvent::Spi(state.shift as u16),
+            MockLine::Fsync => panic!("FSYNC framed {} bits", state.bits),
+        };
+        state.shift = 0;
+        state.bits = 0;
+        // A full log only drops the newest events, earlier ones stay comparable
+        state.events.push(event).ok();
+    }
+
+    fn clock_in(&self, bytes: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        assert!(
+            !state.levels[MockLine::Fsync as usize],
+            "SPI write with FSYNC high"
+        );
+        for &byte in bytes {
+            // MSB first
+            state.shift = (state.shift << 8) | byte as u64;
+            state.bits = state.bits.saturating_add(8);
+        }
+    }
+}
+
+impl Default for MockBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct MockPin<'a> {
+    bus: &'a MockBus,
+    line: MockLine,
+}
+
+impl ErrorType for MockPin<'_> {
+    type Error = Infallible;
+}
+
+impl OutputPin for MockPin<'_> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.bus.drive(self.line, false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.bus.drive(self.line, true);
+        Ok(())
+    }
+}
+
+/// Write-only SPI bus clocking bytes into the AD983x frame FSYNC is holding open.
+pub struct MockSpi<'a> {
+    bus: &'a MockBus,
+}
+
+impl spi::ErrorType for MockSpi<'_> {
+    type Error = Infallible;
+}
+
+impl SpiBus<u8> for MockSpi<'_> {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        words.fill(0);
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.bus.clock_in(words);
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.bus.clock_in(write);
+        read.fill(0);
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus.clock_in(words);
+        words.fill(0);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -3,7 +3,21 @@
 
 mod dds_task;
 pub use dds_task::*;
+mod dds_device;
+pub use dds_device::*;
+#[cfg(not(feature = "ad983x"))]
 mod ad985x;
+#[cfg(not(feature = "ad983x"))]
 pub use ad985x::*;
+#[cfg(all(target_os = "none", not(feature = "ad983x")))]
+mod ad985x_pio;
+#[cfg(all(target_os = "none", not(feature = "ad983x")))]
+pub use ad985x_pio::*;
+#[cfg(feature = "ad983x")]
+mod ad983x;
+#[cfg(feature = "ad983x")]
+pub use ad983x::*;
 mod dds_type;
 pub use dds_type::*;
+#[cfg(test)]
+pub mod mock;
@@ -9,6 +9,21 @@ pub enum FirmwareError {
     Proto(ProtoError),
     Hexa(HexaError),
     OperationStepsFull,
+    FreqOutOfRange,
+    DdsBus,
+    WaveformUnsupported,
+    Storage,
+    NotGenerating,
+    NotPaused,
+    NoStoredOperation,
+    SlotEmpty,
+    StepNotFound,
+    StreamFull,
+    BulkCrc,
+    OperationEmpty,
+    OperationMismatch,
+    ZeroDwell,
+    SessionTooLong,
 }
 
 impl From<ProtoError> for FirmwareError {
@@ -37,6 +52,21 @@ impl FirmwareError {
                 HexaError::InvalidParam => 15,
             },
             FirmwareError::OperationStepsFull => 20,
+            FirmwareError::FreqOutOfRange => 21,
+            FirmwareError::DdsBus => 22,
+            FirmwareError::WaveformUnsupported => 23,
+            FirmwareError::Storage => 24,
+            FirmwareError::NotGenerating => 25,
+            FirmwareError::NotPaused => 26,
+            FirmwareError::NoStoredOperation => 27,
+            FirmwareError::SlotEmpty => 28,
+            FirmwareError::StepNotFound => 29,
+            FirmwareError::StreamFull => 30,
+            FirmwareError::BulkCrc => 31,
+            FirmwareError::OperationEmpty => 32,
+            FirmwareError::OperationMismatch => 33,
+            FirmwareError::ZeroDwell => 34,
+            FirmwareError::SessionTooLong => 35,
         }
     }
 }
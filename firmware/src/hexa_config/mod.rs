@@ -3,9 +3,33 @@
 
 use core::sync::atomic::{AtomicBool, Ordering};
 
+#[cfg(not(feature = "ad983x"))]
+use crate::dds::DdsVariant;
+
 //General configuration constants
 pub const CONF_VERSION: &str = "v1.0.0";
 
+//DDS chip configuration, other boards are built with `--features ad9851` or `ad983x`
+#[cfg(all(feature = "ad9851", feature = "ad983x"))]
+compile_error!("features `ad9851` and `ad983x` select different DDS chips");
+#[cfg(not(any(feature = "ad9851", feature = "ad983x")))]
+pub const CONF_DDS_VARIANT: DdsVariant = DdsVariant::Ad9850;
+#[cfg(not(any(feature = "ad9851", feature = "ad983x")))]
+pub const CONF_DDS_REF_CLK_HZ: u32 = 125_000_000;
+#[cfg(feature = "ad9851")]
+pub const CONF_DDS_VARIANT: DdsVariant = DdsVariant::Ad9851 { x6: true };
+#[cfg(feature = "ad9851")]
+pub const CONF_DDS_REF_CLK_HZ: u32 = 30_000_000;
+#[cfg(feature = "ad983x")]
+pub const CONF_DDS_MCLK_HZ: u32 = 25_000_000;
+#[cfg(feature = "ad983x")]
+pub const CONF_DDS_SPI_HZ: u32 = 10_000_000;
+
+//Flash storage, the last 64 KiB of flash are left out of the firmware image in memory.x
+pub const CONF_FLASH_SIZE: usize = 2 * 1024 * 1024;
+pub const CONF_STORAGE_OFFSET: u32 = 0x1F_0000;
+pub const CONF_STORAGE_SIZE: u32 = 0x1_0000;
+
 //DDS status tracking
 pub static DDS_AVAILABLE: AtomicBool = AtomicBool::new(true);
 pub fn set_dds_available(status: bool) {
@@ -0,0 +1,41 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+//! Firmware domains of hexaGenMini. Everything that touches the RP2040 is
+//! built for the board only; the rest, DDS task included, also builds on the
+//! host for `make test`.
+
+#![no_std]
+
+#[cfg(test)]
+extern crate std;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as Cs;
+use embassy_sync::channel::Channel;
+
+pub mod at;
+pub mod channel;
+pub mod dds;
+pub mod error;
+pub mod hexa_config;
+pub mod rgb;
+pub mod storage;
+pub mod usb;
+
+use crate::channel::*;
+pub const CAP: usize = 16;
+pub static USB_CH: Channel<Cs, Msg, CAP> = Channel::new();
+pub static AT_CH: Channel<Cs, Msg, CAP> = Channel::new();
+pub static RGB_CH: Channel<Cs, Msg, CAP> = Channel::new();
+pub static DDS_CH: Channel<Cs, Msg, CAP> = Channel::new();
+
+/// Host tests share the channels, the flash store and the DDS statics, so
+/// the ones that touch them run one at a time.
+#[cfg(test)]
+pub(crate) fn serial() -> std::sync::MutexGuard<'static, ()> {
+    static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    // A failed test must not fail the ones after it
+    SERIAL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
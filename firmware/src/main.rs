@@ -6,25 +6,10 @@
 
 use defmt::*;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as Cs;
-use embassy_sync::channel::Channel;
 use embassy_sync::mutex::Mutex as AsyncMutex;
+use hexagenmini::{at, dds, hexa_config, rgb, storage, usb};
 use {defmt_rtt as _, panic_probe as _};
 
-mod at;
-mod channel;
-mod dds;
-mod error;
-mod hexa_config;
-mod rgb;
-mod usb;
-
-use crate::channel::*;
-pub const CAP: usize = 16;
-pub static USB_CH: Channel<Cs, Msg, CAP> = Channel::new();
-pub static AT_CH: Channel<Cs, Msg, CAP> = Channel::new();
-pub static RGB_CH: Channel<Cs, Msg, CAP> = Channel::new();
-pub static DDS_CH: Channel<Cs, Msg, CAP> = Channel::new();
-
 embassy_rp::bind_interrupts!(struct IrqUsb {
     USBCTRL_IRQ => embassy_rp::usb::InterruptHandler<embassy_rp::peripherals::USB>;
 });
@@ -54,7 +39,11 @@ async fn main(spawner: embassy_executor::Spawner) {
     //Led module
     info!("Initializing RGB LED");
     let embassy_rp::pio::Pio {
-        mut common, sm0, ..
+        mut common,
+        sm0,
+        #[cfg(not(feature = "ad983x"))]
+        sm1,
+        ..
     } = embassy_rp::pio::Pio::new(p.PIO0, IrqPio);
     let program = embassy_rp::pio_programs::ws2812::PioWs2812Program::new(&mut common);
     let ws2812 = embassy_rp::pio_programs::ws2812::PioWs2812::new(
@@ -67,14 +56,38 @@ async fn main(spawner: embassy_executor::Spawner) {
     let rgb_led = rgb::RgbLed::new(ws2812);
 
     //DDS module
-    let ad9850 = dds::Ad985x::new(
-        embassy_rp::gpio::Output::new(p.PIN_2, embassy_rp::gpio::Level::Low),
-        embassy_rp::gpio::Output::new(p.PIN_3, embassy_rp::gpio::Level::Low),
-        embassy_rp::gpio::Output::new(p.PIN_4, embassy_rp::gpio::Level::Low),
-        embassy_rp::gpio::Output::new(p.PIN_5, embassy_rp::gpio::Level::Low),
-        125_000_000,
-        0,
-    );
+    #[cfg(not(feature = "ad983x"))]
+    let dds = {
+        let program = dds::Ad985xPioProgram::new(&mut common);
+        let link = dds::PioLink::new(
+            &mut common,
+            sm1,
+            p.PIN_2,
+            p.PIN_3,
+            p.PIN_4,
+            embassy_rp::gpio::Output::new(p.PIN_5, embassy_rp::gpio::Level::Low),
+            &program,
+        );
+        dds::Ad985x::new(
+            link,
+            hexa_config::CONF_DDS_VARIANT,
+            hexa_config::CONF_DDS_REF_CLK_HZ,
+        )
+    };
+    #[cfg(feature = "ad983x")]
+    let dds = {
+        let mut spi_cfg = embassy_rp::spi::Config::default();
+        spi_cfg.frequency = hexa_config::CONF_DDS_SPI_HZ;
+        spi_cfg.polarity = embassy_rp::spi::Polarity::IdleHigh;
+        dds::Ad983x::new(
+            embassy_rp::spi::Spi::new_blocking_txonly(p.SPI0, p.PIN_2, p.PIN_3, spi_cfg),
+            embassy_rp::gpio::Output::new(p.PIN_5, embassy_rp::gpio::Level::High),
+            hexa_config::CONF_DDS_MCLK_HZ,
+        )
+    };
+
+    //Storage module, loaded by the DDS task at startup
+    storage::init_storage(embassy_rp::flash::Flash::new_blocking(p.FLASH)).await;
 
     //Dummy Led
     let led = embassy_rp::gpio::Output::new(p.PIN_25, embassy_rp::gpio::Level::Low);
@@ -83,7 +96,7 @@ async fn main(spawner: embassy_executor::Spawner) {
     spawner.spawn(rgb::rgb_task(rgb_led)).unwrap();
     spawner.spawn(usb::dev_task(device)).unwrap();
     spawner.spawn(usb::usb_io_task(midi_mutex)).unwrap();
-    spawner.spawn(dds::dds_task(ad9850)).unwrap();
+    spawner.spawn(dds::dds_task(dds)).unwrap();
     spawner.spawn(main_loop_task(led)).unwrap();
 }
 
@@ -0,0 +1,20 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as Cs;
+
+use crate::dds::Rgb;
+
+/// Colour last set with SETRGB, which the LED returns to after step colours.
+static HOST_RGB: BlockingMutex<Cs, Cell<Rgb>> =
+    BlockingMutex::new(Cell::new(Rgb { r: 0, g: 0, b: 0 }));
+
+pub fn host_rgb() -> Rgb {
+    HOST_RGB.lock(|rgb| rgb.get())
+}
+
+pub fn set_host_rgb(colour: Rgb) {
+    HOST_RGB.lock(|rgb| rgb.set(colour));
+}
@@ -1,7 +1,13 @@
 // SPDX-FileCopyrightText: 2025 hexaTune LLC
 // SPDX-License-Identifier: MIT
 
+#[cfg(target_os = "none")]
 mod rgb_task;
+#[cfg(target_os = "none")]
 pub use rgb_task::*;
+#[cfg(target_os = "none")]
 mod led;
+#[cfg(target_os = "none")]
 pub use led::*;
+mod host_rgb;
+pub use host_rgb::*;
@@ -2,12 +2,12 @@
 // SPDX-License-Identifier: MIT
 
 use defmt::*;
-use {defmt_rtt as _, panic_probe as _};
 
 use crate::AT_CH;
 use crate::RGB_CH;
 use crate::channel::*;
-use crate::rgb::RgbLed;
+use crate::dds::Rgb;
+use crate::rgb::{RgbLed, set_host_rgb};
 
 #[embassy_executor::task]
 pub async fn rgb_task(mut rgb_led: RgbLed) {
@@ -17,8 +17,11 @@ pub async fn rgb_task(mut rgb_led: RgbLed) {
             info!("Setting RGB to ({}, {}, {})", r, g, b);
             rgb_led.set_rgb(r, g, b).await;
             info!("RGB set");
-            info!("Sending DONE from RGB task");
-            AT_CH.send(Msg::Done(id)).await;
+            if let Some(id) = id {
+                set_host_rgb(Rgb { r, g, b });
+                info!("Sending DONE from RGB task");
+                AT_CH.send(Msg::Done(id)).await;
+            }
         }
     }
 }
@@ -0,0 +1,321 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+use core::cell::RefCell;
+use defmt::*;
+#[cfg(target_os = "none")]
+use embassy_rp::flash::{Blocking, Flash};
+#[cfg(target_os = "none")]
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as Cs;
+use embassy_sync::mutex::Mutex;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use hexa_tune_proto_embedded::HexaError;
+
+use crate::dds::Operation;
+use crate::error::FirmwareError;
+#[cfg(target_os = "none")]
+use crate::hexa_config::CONF_FLASH_SIZE;
+use crate::hexa_config::{CONF_STORAGE_OFFSET, CONF_STORAGE_SIZE};
+use crate::storage::{
+    OPERATION_RECORD_LEN, OperationRecord, SLOT_COUNT, SLOT_RECORD_LEN, SlotName, SlotRecord,
+    decode_operation, decode_slot, encode_operation, encode_slot,
+};
+
+#[cfg(target_os = "none")]
+pub type BoardFlash = Flash<'static, FLASH, Blocking, CONF_FLASH_SIZE>;
+/// Host builds keep the storage area in RAM.
+#[cfg(not(target_os = "none"))]
+pub type BoardFlash = crate::storage::RamFlash;
+
+static FLASH_STORE: Mutex<Cs, RefCell<Option<BoardFlash>>> = Mutex::new(RefCell::new(None));
+
+// Storage area layout, one erase sector per record
+const SECTOR: u32 = BoardFlash::ERASE_SIZE as u32;
+const CONFIG_OFFSET: u32 = CONF_STORAGE_OFFSET;
+const OPERATION_OFFSET: u32 = CONF_STORAGE_OFFSET + SECTOR;
+const SLOTS_OFFSET: u32 = CONF_STORAGE_OFFSET + 2 * SECTOR;
+const _: () = core::assert!(SLOT_RECORD_LEN <= SECTOR as usize);
+const _: () = core::assert!(2 + SLOT_COUNT as u32 <= CONF_STORAGE_SIZE / SECTOR);
+
+const CONFIG_MAGIC: [u8; 4] = *b"HXC1";
+const CONFIG_LEN: usize = 9;
+const AUTOSTART_ON: u8 = 1;
+
+/// Settings kept across power cycles.
+#[derive(Clone, Copy, Default)]
+pub struct StoredConfig {
+    /// Reference clock correction in ppb
+    pub clock_ppb: i32,
+    /// Generate the saved operation after power-up
+    pub autostart: bool,
+}
+
+impl StoredConfig {
+    fn to_bytes(self) -> [u8; CONFIG_LEN] {
+        let mut record = [0u8; CONFIG_LEN];
+        record[..4].copy_from_slice(&CONFIG_MAGIC);
+        record[4..8].copy_from_slice(&self.clock_ppb.to_le_bytes());
+        record[8] = if self.autostart { AUTOSTART_ON } else { 0 };
+        record
+    }
+
+    fn from_bytes(record: &[u8; CONFIG_LEN]) -> Option<Self> {
+        // Erased flash reads back as 0xFF and fails the magic check
+        if record[..4] != CONFIG_MAGIC {
+            return None;
+        }
+        let mut ppb = [0u8; 4];
+        ppb.copy_from_slice(&record[4..8]);
+        Some(Self {
+            clock_ppb: i32::from_le_bytes(ppb),
+            // Records written before the flag existed read back as erased
+            autostart: record[8] == AUTOSTART_ON,
+        })
+    }
+}
+
+/// Hand the flash peripheral to the store; must run before any load or store.
+pub async fn init_storage(flash: BoardFlash) {
+    let store = FLASH_STORE.lock().await;
+    store.replace(Some(flash));
+}
+
+/// Read the stored configuration, `None` if nothing valid has been stored yet.
+pub async fn load_config() -> Option<StoredConfig> {
+    let mut record = [0u8; CONFIG_LEN];
+    read(CONFIG_OFFSET, &mut record).await.ok()?;
+    StoredConfig::from_bytes(&record)
+}
+
+/// Change some settings of the stored configuration and write it back.
+pub async fn update_config(
+    update: impl FnOnce(&mut StoredConfig),
+) -> Result<StoredConfig, FirmwareError> {
+    let mut config = load_config().await.unwrap_or_default();
+    update(&mut config);
+    rewrite_sector(CONFIG_OFFSET, &config.to_bytes()).await?;
+    Ok(config)
+}
+
+/// Write `operation` to its flash sector.
+pub async fn store_operation(operation: &Operation) -> Result<(), FirmwareError> {
+    let mut record: OperationRecord = [0u8; OPERATION_RECORD_LEN];
+    let len = encode_operation(operation, &mut record);
+    rewrite_sector(OPERATION_OFFSET, &record[..len]).await
+}
+
+/// Read back the stored operation, checking its CRC.
+pub async fn load_operation() -> Result<Operation, FirmwareError> {
+    let mut record: OperationRecord = [0u8; OPERATION_RECORD_LEN];
+    read(OPERATION_OFFSET, &mut record).await?;
+    decode_operation(&record).ok_or(FirmwareError::NoStoredOperation)
+}
+
+/// Write `operation` under `name` to slot `slot`.
+pub async fn store_slot(
+    slot: u8,
+    name: &SlotName,
+    operation: &Operation,
+) -> Result<(), FirmwareError> {
+    let mut record: SlotRecord = [0u8; SLOT_RECORD_LEN];
+    let len = encode_slot(name, operation, &mut record);
+    rewrite_sector(slot_offset(slot)?, &record[..len]).await
+}
+
+/// Read back slot `slot`, `FirmwareError::SlotEmpty` if it holds nothing valid.
+pub async fn load_slot(slot: u8) -> Result<(SlotName, Operation), FirmwareError> {
+    let mut record: SlotRecord = [0u8; SLOT_RECORD_LEN];
+    read(slot_offset(slot)?, &mut record).await?;
+    decode_slot(&record).ok_or(FirmwareError::SlotEmpty)
+}
+
+/// Erase slot `slot`.
+pub async fn delete_slot(slot: u8) -> Result<(), FirmwareError> {
+    rewrite_sector(slot_offset(slot)?, &[]).await
+}
+
+fn slot_offset(slot: u8) -> Result<u32, FirmwareError> {
+    if slot >= SLOT_COUNT {
+        return Err(FirmwareError::Hexa(HexaError::InvalidParam));
+    }
+    Ok(SLOTS_OFFSET + slot as u32 * SECTOR)
+}
+
+async fn read(offset: u32, buf: &mut [u8]) -> Result<(), FirmwareError> {
+    let store = FLASH_STORE.lock().await;
+    let mut guard = store.borrow_mut();
+    let flash = guard.as_mut().ok_or(FirmwareError::Storage)?;
+    flash.read(offset, buf).map_err(|e| {
+        error!("Flash read at {:#x} failed: {:?}", offset, e);
+        FirmwareError::Storage
+    })
+}
+
+/// Erase the sector at `offset` and write `data` to its start.
+async fn rewrite_sector(offset: u32, data: &[u8]) -> Result<(), FirmwareError> {
+    let store = FLASH_STORE.lock().await;
+    let mut guard = store.borrow_mut();
+    let flash = guard.as_mut().ok_or(FirmwareError::Storage)?;
+    flash
+        .erase(offset, offset + SECTOR)
+        .and_then(|_| match data.is_empty() {
+            true => Ok(()),
+            false => flash.write(offset, data),
+        })
+        .map_err(|e| {
+            error!("Flash write at {:#x} failed: {:?}", offset, e);
+            FirmwareError::Storage
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::dds::{FreqStep, MilliHertz};
+    use crate::serial;
+    use crate::storage::RamFlash;
+    // Not the defmt ones from `super`
+    use core::{assert, assert_eq};
+
+    fn operation(id: u32) -> Operation {
+        let mut operation = Operation::new();
+        operation.set_id(id);
+        operation
+            .add_step(FreqStep {
+                id: 1,
+                freq: MilliHertz(440_000),
+                time_ms: 100,
+                phase: None,
+                sweep: None,
+                glide: None,
+                colour: None,
+            })
+            .ok()
+            .unwrap();
+        operation
+    }
+
+    #[test]
+    fn config_roundtrips() {
+        for config in [
+            StoredConfig::default(),
+            StoredConfig {
+                clock_ppb: -1_234_567,
+                autostart: true,
+            },
+        ] {
+            let record = config.to_bytes();
+            assert_eq!(record[..4], *b"HXC1");
+            let decoded = StoredConfig::from_bytes(&record).unwrap();
+            assert_eq!(decoded.clock_ppb, config.clock_ppb);
+            assert_eq!(decoded.autostart, config.autostart);
+        }
+    }
+
+    #[test]
+    fn config_rejects_erased_flash_and_reads_old_records() {
+        assert!(StoredConfig::from_bytes(&[0xFF; CONFIG_LEN]).is_none());
+        let mut record = StoredConfig {
+            clock_ppb: 500,
+            autostart: true,
+        }
+        .to_bytes();
+        record[0] = b'X';
+        assert!(StoredConfig::from_bytes(&record).is_none());
+
+        // Written before the autostart flag existed
+        record[0] = b'H';
+        record[8] = 0xFF;
+        let decoded = StoredConfig::from_bytes(&record).unwrap();
+        assert_eq!(decoded.clock_ppb, 500);
+        assert!(!decoded.autostart);
+    }
+
+    #[test]
+    fn update_config_keeps_the_other_settings() {
+        let _serial = serial();
+        block_on(async {
+            init_storage(RamFlash::default()).await;
+            assert!(load_config().await.is_none());
+
+            update_config(|config| config.clock_ppb = -42)
+                .await
+                .unwrap();
+            let config = update_config(|config| config.autostart = true)
+                .await
+                .unwrap();
+            assert_eq!(config.clock_ppb, -42);
+            let loaded = load_config().await.unwrap();
+            assert_eq!(loaded.clock_ppb, -42);
+            assert!(loaded.autostart);
+        });
+    }
+
+    #[test]
+    fn stored_operation_reads_back() {
+        let _serial = serial();
+        block_on(async {
+            init_storage(RamFlash::default()).await;
+            assert!(matches!(
+                load_operation().await,
+                Err(FirmwareError::NoStoredOperation)
+            ));
+
+            store_operation(&operation(7)).await.unwrap();
+            store_operation(&operation(8)).await.unwrap();
+            let loaded = load_operation().await.ok().unwrap();
+            assert_eq!(loaded.get_id(), 8);
+            assert!(loaded.get_steps() == operation(8).get_steps());
+            // The config sector is left alone
+            assert!(load_config().await.is_none());
+        });
+    }
+
+    #[test]
+    fn slots_store_load_and_delete() {
+        let _serial = serial();
+        block_on(async {
+            init_storage(RamFlash::default()).await;
+            assert!(matches!(load_slot(0).await, Err(FirmwareError::SlotEmpty)));
+
+            let name = |name| SlotName::try_from(name).unwrap();
+            store_slot(0, &name("first"), &operation(1)).await.unwrap();
+            store_slot(SLOT_COUNT - 1, &name("last"), &operation(2))
+                .await
+                .unwrap();
+            store_operation(&operation(3)).await.unwrap();
+
+            let (first, loaded) = load_slot(0).await.ok().unwrap();
+            assert_eq!(first.as_str(), "first");
+            assert_eq!(loaded.get_id(), 1);
+            let (last, loaded) = load_slot(SLOT_COUNT - 1).await.ok().unwrap();
+            assert_eq!(last.as_str(), "last");
+            assert_eq!(loaded.get_id(), 2);
+            assert!(matches!(load_slot(1).await, Err(FirmwareError::SlotEmpty)));
+
+            delete_slot(0).await.unwrap();
+            assert!(matches!(load_slot(0).await, Err(FirmwareError::SlotEmpty)));
+            // Neither the other slot nor the saved operation goes with it
+            assert_eq!(load_slot(SLOT_COUNT - 1).await.ok().unwrap().1.get_id(), 2);
+            assert_eq!(load_operation().await.ok().unwrap().get_id(), 3);
+        });
+    }
+
+    #[test]
+    fn slots_past_the_last_are_rejected() {
+        let _serial = serial();
+        block_on(async {
+            init_storage(RamFlash::default()).await;
+            let name = SlotName::try_from("x").unwrap();
+            let invalid = |result: Result<(), FirmwareError>| {
+                matches!(result, Err(FirmwareError::Hexa(HexaError::InvalidParam)))
+            };
+            assert!(invalid(store_slot(SLOT_COUNT, &name, &operation(1)).await));
+            assert!(invalid(load_slot(SLOT_COUNT).await.map(|_| ())));
+            assert!(invalid(delete_slot(u8::MAX).await));
+        });
+    }
+}
@@ -0,0 +1,15 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+mod crc;
+pub use crc::*;
+#[cfg(not(target_os = "none"))]
+mod ram_flash;
+#[cfg(not(target_os = "none"))]
+pub use ram_flash::*;
+mod flash_store;
+pub use flash_store::*;
+mod operation_record;
+pub use operation_record::*;
+mod slot_record;
+pub use slot_record::*;
@@ -0,0 +1,331 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+//! Flash image of an `Operation`.
+//!
+//! Header, one fixed-size record per step, then a CRC-32 over everything
+//! before it. All fields are little-endian.
+//!
+//! | offset | header field      |
+//! |--------|-------------------|
+//! | 0      | magic `HXO1`      |
+//! | 4      | operation id, u32 |
+//! | 8      | repeat, u32       |
+//! | 12     | step count, u16   |
+//! | 14     | reserved          |
+//!
+//! | offset | step field                                     |
+//! |--------|------------------------------------------------|
+//! | 0      | step id, u32                                   |
+//! | 4      | frequency in mHz, u64                          |
+//! | 12     | time in ms, u32                                |
+//! | 16     | phase, u8 (`0xFF` keeps the default phase)     |
+//! | 17     | sweep curve, u8 (0 none, 1 linear, 2 log)      |
+//! | 20     | sweep end in mHz, u64                          |
+//! | 28     | sweep update interval in ms, u32               |
+//! | 32     | glide curve, u8 (0 none, 1 linear, 2 exp)      |
+//! | 36     | glide time in ms, u32                          |
+//! | 40     | colour flag, u8 (0 none, 1 RGB follows)        |
+//! | 41     | colour red, green, blue, u8 each               |
+//! | 44     | reserved up to `STEP_LEN`                      |
+//!
+//! Records written before glides and colours existed carry zeros from
+//! offset 32 and read back without either.
+
+use crate::dds::{
+    FreqStep, Glide, MilliHertz, OPERATION_STEPS, Operation, PHASE_STEPS, Rgb, Sweep, SweepCurve,
+};
+use crate::storage::crc32;
+
+const OPERATION_MAGIC: [u8; 4] = *b"HXO1";
+const HEADER_LEN: usize = 16;
+const STEP_LEN: usize = 48;
+const CRC_LEN: usize = 4;
+const NO_PHASE: u8 = 0xFF;
+const COLOUR_SET: u8 = 1;
+
+/// Size of the largest operation record.
+pub const OPERATION_RECORD_LEN: usize = HEADER_LEN + OPERATION_STEPS * STEP_LEN + CRC_LEN;
+
+pub type OperationRecord = [u8; OPERATION_RECORD_LEN];
+
+/// Serialize `operation` into `buf`, returning the record length.
+pub fn encode_operation(operation: &Operation, buf: &mut OperationRecord) -> usize {
+    buf.fill(0);
+    let steps = operation.get_steps();
+    buf[0..4].copy_from_slice(&OPERATION_MAGIC);
+    buf[4..8].copy_from_slice(&operation.get_id().to_le_bytes());
+    buf[8..12].copy_from_slice(&operation.get_repeat().to_le_bytes());
+    buf[12..14].copy_from_slice(&(steps.len() as u16).to_le_bytes());
+
+    for (i, step) in steps.iter().enumerate() {
+        let rec = &mut buf[HEADER_LEN + i * STEP_LEN..HEADER_LEN + (i + 1) * STEP_LEN];
+        rec[0..4].copy_from_slice(&step.id.to_le_bytes());
+        rec[4..12].copy_from_slice(&step.freq.0.to_le_bytes());
+        rec[12..16].copy_from_slice(&step.time_ms.to_le_bytes());
+        rec[16] = step.phase.unwrap_or(NO_PHASE);
+        if let Some(sweep) = step.sweep {
+            rec[17] = encode_curve(sweep.curve);
+            rec[20..28].copy_from_slice(&sweep.end.0.to_le_bytes());
+            rec[28..32].copy_from_slice(&sweep.update_ms.to_le_bytes());
+        }
+        if let Some(glide) = step.glide {
+            rec[32] = encode_curve(glide.curve);
+            rec[36..40].copy_from_slice(&glide.time_ms.to_le_bytes());
+        }
+        if let Some(Rgb { r, g, b }) = step.colour {
+            rec[40..44].copy_from_slice(&[COLOUR_SET, r, g, b]);
+        }
+    }
+
+    let body = HEADER_LEN + steps.len() * STEP_LEN;
+    let crc = crc32(&buf[..body]);
+    buf[body..body + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+    body + CRC_LEN
+}
+
+/// Parse an operation record, `None` if it is missing, corrupt or malformed.
+pub fn decode_operation(buf: &[u8]) -> Option<Operation> {
+    if buf.len() < HEADER_LEN || buf[0..4] != OPERATION_MAGIC {
+        return None;
+    }
+    let count = u16::from_le_bytes([buf[12], buf[13]]) as usize;
+    let body = HEADER_LEN + count * STEP_LEN;
+    if count > OPERATION_STEPS || buf.len() < body + CRC_LEN {
+        return None;
+    }
+    if crc32(&buf[..body]) != read_u32(&buf[body..]) {
+        return None;
+    }
+
+    let mut operation = Operation::new();
+    operation.set_id(read_u32(&buf[4..]));
+    operation.set_repeat(read_u32(&buf[8..]));
+    for i in 0..count {
+        let rec = &buf[HEADER_LEN + i * STEP_LEN..HEADER_LEN + (i + 1) * STEP_LEN];
+        let phase = match rec[16] {
+            NO_PHASE => None,
+            p if p < PHASE_STEPS => Some(p),
+            _ => return None,
+        };
+        let sweep = decode_curve(rec[17])?.map(|curve| Sweep {
+            end: MilliHertz(read_u64(&rec[20..])),
+            curve,
+            update_ms: read_u32(&rec[28..]),
+        });
+        let time_ms = read_u32(&rec[12..]);
+        let glide = decode_curve(rec[32])?.map(|curve| Glide {
+            time_ms: read_u32(&rec[36..]),
+            curve,
+        });
+        if glide.is_some_and(|glide| glide.time_ms > time_ms) {
+            return None;
+        }
+        let colour = match rec[40] {
+            0 => None,
+            COLOUR_SET => Some(Rgb {
+                r: rec[41],
+                g: rec[42],
+                b: rec[43],
+            }),
+            _ => return None,
+        };
+        operation
+            .add_step(FreqStep {
+                id: read_u32(&rec[0..]),
+                freq: MilliHertz(read_u64(&rec[4..])),
+                time_ms,
+                phase,
+                sweep,
+                glide,
+                colour,
+            })
+            .ok()?;
+    }
+    Some(operation)
+}
+
+fn encode_curve(curve: SweepCurve) -> u8 {
+    match curve {
+        SweepCurve::Linear => 1,
+        SweepCurve::Logarithmic => 2,
+    }
+}
+
+/// `Some(None)` for 0 (no curve), `None` for an unknown value.
+fn decode_curve(byte: u8) -> Option<Option<SweepCurve>> {
+    match byte {
+        0 => Some(None),
+        1 => Some(Some(SweepCurve::Linear)),
+        2 => Some(Some(SweepCurve::Logarithmic)),
+        _ => None,
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(word)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0u8; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(id: u32, hz: u64) -> FreqStep {
+        FreqStep {
+            id,
+            freq: MilliHertz(hz * 1000),
+            time_ms: 1000,
+            phase: None,
+            sweep: None,
+            glide: None,
+            colour: None,
+        }
+    }
+
+    /// An operation using every step field, with its record.
+    fn sample() -> (Operation, OperationRecord, usize) {
+        let mut operation = Operation::new();
+        operation.set_id(42);
+        operation.set_repeat(3);
+        operation.add_step(step(1, 440)).ok().unwrap();
+        operation
+            .add_step(FreqStep {
+                phase: Some(PHASE_STEPS - 1),
+                sweep: Some(Sweep {
+                    end: MilliHertz(880_000),
+                    curve: SweepCurve::Logarithmic,
+                    update_ms: 10,
+                }),
+                ..step(2, 220)
+            })
+            .ok()
+            .unwrap();
+        operation
+            .add_step(FreqStep {
+                glide: Some(Glide {
+                    time_ms: 250,
+                    curve: SweepCurve::Linear,
+                }),
+                colour: Some(Rgb { r: 1, g: 2, b: 3 }),
+                ..step(3, 7_830)
+            })
+            .ok()
+            .unwrap();
+        let mut record = [0u8; OPERATION_RECORD_LEN];
+        let len = encode_operation(&operation, &mut record);
+        (operation, record, len)
+    }
+
+    /// Put a valid CRC back after editing the body of `record`.
+    fn reseal(record: &mut OperationRecord, len: usize) {
+        let crc = crc32(&record[..len - CRC_LEN]);
+        record[len - CRC_LEN..len].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn operation_roundtrips() {
+        let (operation, record, len) = sample();
+        assert_eq!(len, HEADER_LEN + 3 * STEP_LEN + CRC_LEN);
+        assert_eq!(record[..4], *b"HXO1");
+
+        let decoded = decode_operation(&record[..len]).unwrap();
+        assert_eq!(decoded.get_id(), 42);
+        assert_eq!(decoded.get_repeat(), 3);
+        assert!(decoded.get_steps() == operation.get_steps());
+    }
+
+    #[test]
+    fn full_and_empty_operations_roundtrip() {
+        let mut operation = Operation::new();
+        let mut record = [0u8; OPERATION_RECORD_LEN];
+        let len = encode_operation(&operation, &mut record);
+        assert!(
+            decode_operation(&record[..len])
+                .unwrap()
+                .get_steps()
+                .is_empty()
+        );
+
+        for id in 0..OPERATION_STEPS as u32 {
+            operation.add_step(step(id, 100 + id as u64)).ok().unwrap();
+        }
+        assert_eq!(
+            encode_operation(&operation, &mut record),
+            OPERATION_RECORD_LEN
+        );
+        let decoded = decode_operation(&record).unwrap();
+        assert!(decoded.get_steps() == operation.get_steps());
+    }
+
+    #[test]
+    fn rejects_erased_and_foreign_records() {
+        assert!(decode_operation(&[0xFF; OPERATION_RECORD_LEN]).is_none());
+        let (_, mut record, len) = sample();
+        record[3] = b'2';
+        reseal(&mut record, len);
+        assert!(decode_operation(&record[..len]).is_none());
+    }
+
+    #[test]
+    fn rejects_a_bad_crc() {
+        let (_, record, len) = sample();
+        for at in [4, HEADER_LEN + 5, len - 1] {
+            let mut corrupt = record;
+            corrupt[at] ^= 0x10;
+            assert!(decode_operation(&corrupt[..len]).is_none(), "byte {}", at);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let (_, record, len) = sample();
+        assert!(decode_operation(&record[..len - 1]).is_none());
+        assert!(decode_operation(&record[..HEADER_LEN - 1]).is_none());
+        assert!(decode_operation(&[]).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_steps() {
+        let (_, record, len) = sample();
+        let at = |step: usize, field: usize| HEADER_LEN + step * STEP_LEN + field;
+        let edits: [(usize, u8); 5] = [
+            // Phase out of range
+            (at(0, 16), PHASE_STEPS),
+            // Unknown sweep and glide curves
+            (at(1, 17), 3),
+            (at(2, 32), 3),
+            // Glide longer than its step
+            (at(2, 37), 0x10),
+            // Colour flag neither 0 nor 1
+            (at(2, 40), 2),
+        ];
+        for (offset, value) in edits {
+            let mut corrupt = record;
+            corrupt[offset] = value;
+            reseal(&mut corrupt, len);
+            assert!(
+                decode_operation(&corrupt[..len]).is_none(),
+                "byte {}",
+                offset
+            );
+        }
+
+        // More steps than an operation holds
+        let mut corrupt = [0u8; OPERATION_RECORD_LEN + STEP_LEN];
+        corrupt[..OPERATION_RECORD_LEN].copy_from_slice(&record);
+        corrupt[0..4].copy_from_slice(&OPERATION_MAGIC);
+        corrupt[12..14].copy_from_slice(&(OPERATION_STEPS as u16 + 1).to_le_bytes());
+        let body = HEADER_LEN + (OPERATION_STEPS + 1) * STEP_LEN;
+        let crc = crc32(&corrupt[..body]);
+        corrupt[body..].copy_from_slice(&crc.to_le_bytes());
+        assert!(decode_operation(&corrupt).is_none());
+    }
+}
@@ -0,0 +1,86 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+//! Flash stand-in for host builds: the storage area held in RAM, addressed
+//! with the same absolute offsets as the RP2040 flash.
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::hexa_config::{CONF_STORAGE_OFFSET, CONF_STORAGE_SIZE};
+
+#[derive(Debug, defmt::Format)]
+pub struct RamFlashError;
+
+impl NorFlashError for RamFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        NorFlashErrorKind::OutOfBounds
+    }
+}
+
+pub struct RamFlash {
+    data: [u8; CONF_STORAGE_SIZE as usize],
+}
+
+impl RamFlash {
+    /// A storage area that reads back erased.
+    pub const fn new() -> Self {
+        Self {
+            data: [0xFF; CONF_STORAGE_SIZE as usize],
+        }
+    }
+
+    fn range(&self, offset: u32, len: usize) -> Result<core::ops::Range<usize>, RamFlashError> {
+        let start = offset
+            .checked_sub(CONF_STORAGE_OFFSET)
+            .ok_or(RamFlashError)? as usize;
+        match start + len <= self.data.len() {
+            true => Ok(start..start + len),
+            false => Err(RamFlashError),
+        }
+    }
+}
+
+impl Default for RamFlash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = RamFlashError;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, bytes.len())?;
+        bytes.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 1;
+    // Sector size of the RP2040 flash
+    const ERASE_SIZE: usize = 4096;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let len = to.checked_sub(from).ok_or(RamFlashError)? as usize;
+        let range = self.range(from, len)?;
+        self.data[range].fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, bytes.len())?;
+        self.data[range].copy_from_slice(bytes);
+        Ok(())
+    }
+}
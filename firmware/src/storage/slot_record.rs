@@ -0,0 +1,183 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+//! Flash image of a named operation slot: a name header followed by an
+//! operation record (see `operation_record`).
+//!
+//! | offset | header field                 |
+//! |--------|------------------------------|
+//! | 0      | magic `HXS1`                 |
+//! | 4      | name length, u8              |
+//! | 5      | name, zero padded            |
+//! | 24     | CRC-32 of bytes 0..24        |
+//! | 28     | reserved                     |
+
+use heapless::String;
+
+use crate::dds::Operation;
+use crate::storage::{
+    OPERATION_RECORD_LEN, OperationRecord, crc32, decode_operation, encode_operation,
+};
+
+/// Number of named operation slots in flash.
+pub const SLOT_COUNT: u8 = 8;
+/// Longest slot name.
+pub const SLOT_NAME_LEN: usize = 16;
+
+pub type SlotName = String<SLOT_NAME_LEN>;
+
+const SLOT_MAGIC: [u8; 4] = *b"HXS1";
+const SLOT_HEADER_LEN: usize = 32;
+const NAME_OFFSET: usize = 5;
+const NAME_CRC_OFFSET: usize = 24;
+
+/// Size of the largest slot record.
+pub const SLOT_RECORD_LEN: usize = SLOT_HEADER_LEN + OPERATION_RECORD_LEN;
+
+pub type SlotRecord = [u8; SLOT_RECORD_LEN];
+
+/// Slot names are kept to characters that survive the AT parameter syntax.
+pub fn is_valid_slot_name(name: &[u8]) -> bool {
+    !name.is_empty()
+        && name.len() <= SLOT_NAME_LEN
+        && name
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+/// Serialize a named operation into `buf`, returning the record length.
+pub fn encode_slot(name: &SlotName, operation: &Operation, buf: &mut SlotRecord) -> usize {
+    let (header, body) = buf.split_at_mut(SLOT_HEADER_LEN);
+    header.fill(0);
+    header[0..4].copy_from_slice(&SLOT_MAGIC);
+    header[4] = name.len() as u8;
+    header[NAME_OFFSET..NAME_OFFSET + name.len()].copy_from_slice(name.as_bytes());
+    let crc = crc32(&header[..NAME_CRC_OFFSET]);
+    header[NAME_CRC_OFFSET..NAME_CRC_OFFSET + 4].copy_from_slice(&crc.to_le_bytes());
+
+    let mut record: OperationRecord = [0u8; OPERATION_RECORD_LEN];
+    let len = encode_operation(operation, &mut record);
+    body[..len].copy_from_slice(&record[..len]);
+    SLOT_HEADER_LEN + len
+}
+
+/// Parse a slot record, `None` if the slot is empty or corrupt.
+pub fn decode_slot(buf: &SlotRecord) -> Option<(SlotName, Operation)> {
+    let header = &buf[..SLOT_HEADER_LEN];
+    if header[0..4] != SLOT_MAGIC {
+        return None;
+    }
+    let mut crc = [0u8; 4];
+    crc.copy_from_slice(&header[NAME_CRC_OFFSET..NAME_CRC_OFFSET + 4]);
+    if crc32(&header[..NAME_CRC_OFFSET]) != u32::from_le_bytes(crc) {
+        return None;
+    }
+    let len = header[4] as usize;
+    let name = header.get(NAME_OFFSET..NAME_OFFSET + len)?;
+    if !is_valid_slot_name(name) {
+        return None;
+    }
+    let name = SlotName::try_from(core::str::from_utf8(name).ok()?).ok()?;
+    let operation = decode_operation(&buf[SLOT_HEADER_LEN..])?;
+    Some((name, operation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dds::{FreqStep, MilliHertz};
+
+    fn sample() -> (SlotRecord, usize) {
+        let mut operation = Operation::new();
+        operation.set_id(5);
+        operation
+            .add_step(FreqStep {
+                id: 1,
+                freq: MilliHertz(432_000),
+                time_ms: 2000,
+                phase: Some(8),
+                sweep: None,
+                glide: None,
+                colour: None,
+            })
+            .ok()
+            .unwrap();
+        let mut record = [0u8; SLOT_RECORD_LEN];
+        let len = encode_slot(
+            &SlotName::try_from("warm-up_1.a").unwrap(),
+            &operation,
+            &mut record,
+        );
+        (record, len)
+    }
+
+    /// Put a valid name CRC back after editing the header of `record`.
+    fn reseal(record: &mut SlotRecord) {
+        let crc = crc32(&record[..NAME_CRC_OFFSET]);
+        record[NAME_CRC_OFFSET..NAME_CRC_OFFSET + 4].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn slot_names_are_checked() {
+        for name in ["a", "A-b_c.9", "0123456789abcdef"] {
+            assert!(is_valid_slot_name(name.as_bytes()), "{}", name);
+        }
+        for name in ["", "0123456789abcdefg", "a b", "a,b", "a\"b", "é"] {
+            assert!(!is_valid_slot_name(name.as_bytes()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn slot_roundtrips() {
+        let (record, len) = sample();
+        assert_eq!(record[..4], *b"HXS1");
+        assert_eq!(record[SLOT_HEADER_LEN..SLOT_HEADER_LEN + 4], *b"HXO1");
+        assert!(len < SLOT_RECORD_LEN);
+
+        let (name, operation) = decode_slot(&record).unwrap();
+        assert_eq!(name.as_str(), "warm-up_1.a");
+        assert_eq!(operation.get_id(), 5);
+        assert_eq!(operation.get_steps()[0].phase, Some(8));
+    }
+
+    #[test]
+    fn rejects_erased_and_corrupt_headers() {
+        assert!(decode_slot(&[0xFF; SLOT_RECORD_LEN]).is_none());
+        let (record, _) = sample();
+
+        let mut corrupt = record;
+        corrupt[0] = b'X';
+        reseal(&mut corrupt);
+        assert!(decode_slot(&corrupt).is_none());
+
+        // Name changed without its CRC
+        let mut corrupt = record;
+        corrupt[NAME_OFFSET] = b'W';
+        assert!(decode_slot(&corrupt).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        let (record, _) = sample();
+        let edits: [(usize, u8); 3] = [
+            (NAME_OFFSET + 4, b' '),
+            (4, 0),
+            // Longer than a name can be
+            (4, SLOT_NAME_LEN as u8 + 1),
+        ];
+        for (offset, value) in edits {
+            let mut corrupt = record;
+            corrupt[offset] = value;
+            reseal(&mut corrupt);
+            assert!(decode_slot(&corrupt).is_none(), "byte {}", offset);
+        }
+    }
+
+    #[test]
+    fn rejects_a_corrupt_operation() {
+        let (record, len) = sample();
+        let mut corrupt = record;
+        corrupt[len - 1] ^= 1;
+        assert!(decode_slot(&corrupt).is_none());
+    }
+}
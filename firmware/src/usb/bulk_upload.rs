@@ -0,0 +1,323 @@
+// SPDX-FileCopyrightText: 2025 hexaTune LLC
+// SPDX-License-Identifier: MIT
+
+//! Binary bulk upload of operation steps over SysEx.
+//!
+//! A bulk payload is `BULK_MARKER` followed by a chunk packed six bits per
+//! byte, most significant bits first, with `0x40` added so every byte stays
+//! in `0x40..=0x7F`: 7-bit safe, and free of the zero bytes USB MIDI
+//! depacketizing drops as padding. Three chunk bytes take four payload bytes;
+//! a trailing one or two bytes take two or three.
+//!
+//! The unpacked chunk is little-endian:
+//!
+//! | offset | field                                          |
+//! |--------|------------------------------------------------|
+//! | 0      | chunk id, u32, also the step id of the first step |
+//! | 4      | step count, u8                                 |
+//! | 5      | per step: frequency in mHz u64, time in ms u32 |
+//! | ...    | CRC-32 of everything before it                 |
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as Cs;
+use heapless::Vec;
+use hexa_tune_proto::ProtoError;
+
+use crate::at::{encode_response, u32_to_ascii_buf};
+use crate::channel::MsgString;
+use crate::dds::{FreqStep, MilliHertz};
+use crate::error::{FirmwareError, HexaError};
+use crate::storage::crc32;
+
+/// First payload byte of a bulk chunk (the SysEx non-commercial id); AT
+/// payloads start with `A`.
+pub const BULK_MARKER: u8 = 0x7D;
+/// Most steps in one chunk.
+pub const BULK_MAX_STEPS: usize = 16;
+/// Most verified chunks waiting for the DDS task at once.
+pub const BULK_QUEUE: usize = 4;
+
+const PACK_OFFSET: u8 = 0x40;
+const HEADER_LEN: usize = 5;
+const RECORD_LEN: usize = 12;
+const CRC_LEN: usize = 4;
+const CHUNK_MAX_LEN: usize = HEADER_LEN + BULK_MAX_STEPS * RECORD_LEN + CRC_LEN;
+
+/// Longest bulk payload, marker included.
+pub const BULK_PAYLOAD_MAX_LEN: usize = 1 + CHUNK_MAX_LEN.div_ceil(3) * 4;
+
+/// A verified chunk of steps waiting for the DDS task to append it.
+pub struct BulkChunk {
+    pub id: u32,
+    pub steps: Vec<FreqStep, BULK_MAX_STEPS>,
+}
+
+/// Verified chunks on their way to the DDS task, looked up by chunk id.
+static BULK_CHUNKS: BlockingMutex<Cs, RefCell<Vec<BulkChunk, BULK_QUEUE>>> =
+    BlockingMutex::new(RefCell::new(Vec::new()));
+
+/// Queue a verified chunk until `take_bulk` picks it up. A chunk whose id is
+/// already waiting, or one the queue has no room for, is refused with
+/// `DdsBusy` and the waiting chunks are left as they are.
+pub fn queue_bulk(chunk: BulkChunk) -> Result<(), FirmwareError> {
+    let busy = FirmwareError::Hexa(HexaError::DdsBusy);
+    BULK_CHUNKS.lock(|queue| {
+        let mut queue = queue.borrow_mut();
+        if queue.iter().any(|waiting| waiting.id == chunk.id) {
+            return Err(busy);
+        }
+        queue.push(chunk).map_err(|_| busy)
+    })
+}
+
+/// Remove the waiting chunk with id `id` from the queue.
+pub fn take_bulk(id: u32) -> Option<BulkChunk> {
+    BULK_CHUNKS.lock(|queue| {
+        let mut queue = queue.borrow_mut();
+        let index = queue.iter().position(|chunk| chunk.id == id)?;
+        Some(queue.swap_remove(index))
+    })
+}
+
+/// Decode a bulk payload (marker included). The error carries the chunk id
+/// when it could be read, 0 otherwise.
+pub fn decode_bulk(payload: &[u8]) -> Result<BulkChunk, (u32, FirmwareError)> {
+    let malformed = FirmwareError::Proto(ProtoError::InvalidSysex);
+    let mut chunk = [0u8; CHUNK_MAX_LEN];
+    let len = unpack(payload.get(1..).unwrap_or(&[]), &mut chunk).ok_or((0, malformed))?;
+    let chunk = &chunk[..len];
+    if len < HEADER_LEN + CRC_LEN {
+        return Err((0, malformed));
+    }
+
+    let id = read_u32(&chunk[0..]);
+    let count = chunk[4] as usize;
+    let body = HEADER_LEN + count * RECORD_LEN;
+    if count > BULK_MAX_STEPS || len != body + CRC_LEN {
+        return Err((id, malformed));
+    }
+    if crc32(&chunk[..body]) != read_u32(&chunk[body..]) {
+        return Err((id, FirmwareError::BulkCrc));
+    }
+
+    let mut steps = Vec::new();
+    for (i, rec) in chunk[HEADER_LEN..body].chunks_exact(RECORD_LEN).enumerate() {
+        let mut freq = [0u8; 8];
+        freq.copy_from_slice(&rec[0..8]);
+        steps
+            .push(FreqStep {
+                id: id.wrapping_add(i as u32),
+                freq: MilliHertz(u64::from_le_bytes(freq)),
+                time_ms: read_u32(&rec[8..]),
+                phase: None,
+                sweep: None,
+                glide: None,
+                colour: None,
+            })
+            .ok();
+    }
+    Ok(BulkChunk { id, steps })
+}
+
+/// Build the ACK `AT+BULK=id#ACK#steps`, with the step count of the operation,
+/// or with the free buffer space when the chunk went to an open stream.
+pub fn encode_ack(id: u32, steps: usize) -> Result<MsgString, FirmwareError> {
+    let mut steps_buf = [0u8; 10];
+    let steps_len = u32_to_ascii_buf(steps as u32, &mut steps_buf);
+    encode_response(b"BULK", id, &[b"ACK", &steps_buf[..steps_len]])
+}
+
+/// Build the NAK `AT+BULK=id#NAK#code`, with the code `AT+ERROR` would carry.
+pub fn encode_nak(id: u32, e: &FirmwareError) -> Result<MsgString, FirmwareError> {
+    let mut code_buf = [0u8; 10];
+    let code_len = u32_to_ascii_buf(e.error_code() as u32, &mut code_buf);
+    encode_response(b"BULK", id, &[b"NAK", &code_buf[..code_len]])
+}
+
+/// Unpack six bits per byte into `out`, `None` on a byte outside
+/// `0x40..=0x7F` or a length that no chunk packs to.
+fn unpack(packed: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    for group in packed.chunks(4) {
+        if group.len() == 1 {
+            return None;
+        }
+        let mut bits = 0u32;
+        for &b in group {
+            if !(PACK_OFFSET..=0x7F).contains(&b) {
+                return None;
+            }
+            bits = bits << 6 | (b - PACK_OFFSET) as u32;
+        }
+        // Left-align the group to 24 bits, a short group carries 1 or 2 bytes
+        bits <<= 6 * (4 - group.len());
+        let bytes = group.len() - 1;
+        if len + bytes > out.len() {
+            return None;
+        }
+        for i in 0..bytes {
+            out[len + i] = (bits >> (16 - 8 * i)) as u8;
+        }
+        len += bytes;
+    }
+    Some(len)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec as StdVec;
+
+    use super::*;
+    use crate::serial;
+
+    /// Inverse of `unpack`.
+    fn pack(bytes: &[u8]) -> StdVec<u8> {
+        let mut packed = StdVec::new();
+        for group in bytes.chunks(3) {
+            let mut bits = 0u32;
+            for (i, &b) in group.iter().enumerate() {
+                bits |= (b as u32) << (16 - 8 * i);
+            }
+            for i in 0..=group.len() {
+                packed.push(((bits >> (18 - 6 * i)) & 0x3F) as u8 + PACK_OFFSET);
+            }
+        }
+        packed
+    }
+
+    /// Payload of chunk `id` carrying `steps` as (mHz, ms), marker included.
+    fn payload(id: u32, steps: &[(u64, u32)]) -> StdVec<u8> {
+        let mut chunk = StdVec::new();
+        chunk.extend_from_slice(&id.to_le_bytes());
+        chunk.push(steps.len() as u8);
+        for &(freq, time_ms) in steps {
+            chunk.extend_from_slice(&freq.to_le_bytes());
+            chunk.extend_from_slice(&time_ms.to_le_bytes());
+        }
+        let crc = crc32(&chunk);
+        chunk.extend_from_slice(&crc.to_le_bytes());
+        let mut payload = std::vec![BULK_MARKER];
+        payload.extend(pack(&chunk));
+        payload
+    }
+
+    fn unpacked(packed: &[u8]) -> Option<StdVec<u8>> {
+        let mut out = [0u8; 8];
+        unpack(packed, &mut out).map(|len| out[..len].to_vec())
+    }
+
+    #[test]
+    fn unpack_takes_six_bits_per_byte_msb_first() {
+        // 0x12 0x34 0x56 = 000100 100011 010001 010110
+        assert_eq!(
+            unpacked(&[0x44, 0x63, 0x51, 0x56]),
+            Some(std::vec![0x12, 0x34, 0x56])
+        );
+        assert_eq!(unpacked(&[0x40; 4]), Some(std::vec![0; 3]));
+        assert_eq!(unpacked(&[0x7F; 4]), Some(std::vec![0xFF; 3]));
+        // Trailing groups of two and three bytes carry one and two chunk bytes
+        assert_eq!(unpacked(&[0x6A, 0x70]), Some(std::vec![0xAB]));
+        assert_eq!(unpacked(&[0x6A, 0x7F, 0x7C]), Some(std::vec![0xAB, 0xFF]));
+    }
+
+    #[test]
+    fn pack_stays_in_the_7bit_nonzero_range() {
+        let bytes: StdVec<u8> = (0..=255).collect();
+        let packed = pack(&bytes);
+        assert!(packed.iter().all(|b| (0x40..=0x7F).contains(b)));
+        let mut out = [0u8; 256];
+        assert_eq!(unpack(&packed, &mut out), Some(256));
+        assert_eq!(out.as_slice(), bytes.as_slice());
+    }
+
+    #[test]
+    fn unpack_rejects_bad_bytes_and_lengths() {
+        assert_eq!(unpacked(&[0x44, 0x3F, 0x51, 0x56]), None);
+        assert_eq!(unpacked(&[0x44, 0x80]), None);
+        // A lone trailing byte is never produced by packing
+        assert_eq!(unpacked(&[0x44, 0x63, 0x51, 0x56, 0x44]), None);
+        // More than the output holds
+        assert_eq!(unpacked(&[0x40; 12]), None);
+    }
+
+    #[test]
+    fn decode_bulk_reads_steps() {
+        let chunk = decode_bulk(&payload(7, &[(440_000, 100), (123_456_789, 5)]))
+            .ok()
+            .unwrap();
+        assert_eq!(chunk.id, 7);
+        let steps: StdVec<_> = chunk
+            .steps
+            .iter()
+            .map(|step| (step.id, step.freq.0, step.time_ms))
+            .collect();
+        assert_eq!(steps, [(7, 440_000, 100), (8, 123_456_789, 5)]);
+    }
+
+    #[test]
+    fn decode_bulk_rejects_crc_mismatch() {
+        let mut frame = payload(9, &[(440_000, 100)]);
+        // Flip the low bit of the last step byte before the CRC
+        let last = frame.len() - 7;
+        frame[last] ^= 1;
+        assert!(matches!(
+            decode_bulk(&frame),
+            Err((9, FirmwareError::BulkCrc))
+        ));
+    }
+
+    #[test]
+    fn decode_bulk_rejects_truncated_frames() {
+        let frame = payload(11, &[(440_000, 100), (880_000, 100)]);
+        // One whole group short: the count no longer matches the length
+        assert!(matches!(
+            decode_bulk(&frame[..frame.len() - 4]),
+            Err((11, FirmwareError::Proto(ProtoError::InvalidSysex)))
+        ));
+        // Too short for a header and CRC
+        assert!(matches!(
+            decode_bulk(&frame[..5]),
+            Err((0, FirmwareError::Proto(ProtoError::InvalidSysex)))
+        ));
+        assert!(matches!(
+            decode_bulk(&[BULK_MARKER]),
+            Err((0, FirmwareError::Proto(ProtoError::InvalidSysex)))
+        ));
+    }
+
+    #[test]
+    fn decode_bulk_rejects_too_many_steps() {
+        let steps = [(440_000, 1); BULK_MAX_STEPS + 1];
+        let frame = payload(3, &steps);
+        assert!(frame.len() > BULK_PAYLOAD_MAX_LEN);
+        assert!(matches!(
+            decode_bulk(&frame),
+            Err((0, FirmwareError::Proto(ProtoError::InvalidSysex)))
+        ));
+    }
+
+    #[test]
+    fn queued_chunks_are_taken_by_id() {
+        // BULK_CHUNKS is shared with the DDS task tests
+        let _serial = serial();
+        let chunk = |id| decode_bulk(&payload(id, &[(440_000, 100)])).ok().unwrap();
+        queue_bulk(chunk(100)).unwrap();
+        queue_bulk(chunk(200)).unwrap();
+        // A second copy of a waiting chunk leaves the first in place
+        assert!(matches!(
+            queue_bulk(chunk(100)),
+            Err(FirmwareError::Hexa(HexaError::DdsBusy))
+        ));
+
+        assert_eq!(take_bulk(200).map(|chunk| chunk.id), Some(200));
+        assert_eq!(take_bulk(100).map(|chunk| chunk.id), Some(100));
+        assert!(take_bulk(100).is_none());
+    }
+}
@@ -1,7 +1,13 @@
 // SPDX-FileCopyrightText: 2025 hexaTune LLC
 // SPDX-License-Identifier: MIT
 
+#[cfg(target_os = "none")]
 mod usb_midi;
+#[cfg(target_os = "none")]
 pub use usb_midi::*;
+#[cfg(target_os = "none")]
 mod usb_task;
+#[cfg(target_os = "none")]
 pub use usb_task::*;
+mod bulk_upload;
+pub use bulk_upload::*;
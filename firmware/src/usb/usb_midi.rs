@@ -1,16 +1,34 @@
 // SPDX-FileCopyrightText: 2025 hexaTune LLC
 // SPDX-License-Identifier: MIT
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use embassy_usb::class::midi::MidiClass;
-use embassy_usb::{Builder, Config};
+use embassy_usb::{Builder, Config, Handler};
 use static_cell::StaticCell;
 
-use {defmt_rtt as _, panic_probe as _};
-
 pub type MyDriver<'d> = embassy_rp::usb::Driver<'d, embassy_rp::peripherals::USB>;
 pub type MyUsbDevice<'d> = embassy_usb::UsbDevice<'d, MyDriver<'d>>;
 pub type MyMidiClass<'d> = embassy_usb::class::midi::MidiClass<'d, MyDriver<'d>>;
 
+// Set while a host has the device configured, so nothing reads our IN endpoint otherwise
+static USB_CONFIGURED: AtomicBool = AtomicBool::new(false);
+pub fn is_usb_configured() -> bool {
+    USB_CONFIGURED.load(Ordering::SeqCst)
+}
+
+struct UsbState;
+
+impl Handler for UsbState {
+    fn reset(&mut self) {
+        USB_CONFIGURED.store(false, Ordering::SeqCst);
+    }
+
+    fn configured(&mut self, configured: bool) {
+        USB_CONFIGURED.store(configured, Ordering::SeqCst);
+    }
+}
+
 pub struct UsbMidi {
     pub device: MyUsbDevice<'static>,
     pub midi: MyMidiClass<'static>,
@@ -31,6 +49,7 @@ pub fn init(driver: MyDriver<'static>) -> UsbMidi {
     static BOS_DESC: StaticCell<[u8; 256]> = StaticCell::new();
     static MS_OS_DESC: StaticCell<[u8; 256]> = StaticCell::new();
     static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+    static USB_STATE: StaticCell<UsbState> = StaticCell::new();
 
     let config_desc = CONFIG_DESC.init([0; 256]);
     let bos_desc = BOS_DESC.init([0; 256]);
@@ -39,6 +58,8 @@ pub fn init(driver: MyDriver<'static>) -> UsbMidi {
 
     let mut builder = Builder::new(driver, cfg, config_desc, bos_desc, ms_os_desc, control_buf);
 
+    builder.handler(USB_STATE.init(UsbState));
+
     let midi = MidiClass::new(&mut builder, 1, 1, 64);
 
     let dev = builder.build();
@@ -5,15 +5,23 @@ use defmt::{error, info};
 use embassy_futures::select::{Either, select};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as Cs;
 use embassy_sync::mutex::Mutex;
+use heapless::Vec;
 
 use hexa_tune_proto::sysex;
 use hexa_tune_proto::usb_midi;
 
 use crate::AT_CH;
 use crate::USB_CH;
+use crate::at::reply;
 use crate::channel::*;
 use crate::error::FirmwareError;
-use crate::usb::{MyMidiClass, MyUsbDevice};
+use crate::usb::{
+    BULK_MARKER, BULK_PAYLOAD_MAX_LEN, MyMidiClass, MyUsbDevice, decode_bulk, encode_nak,
+    is_usb_configured, queue_bulk,
+};
+
+/// Longest SysEx message accepted, framing included.
+const SYSEX_RX_LEN: usize = BULK_PAYLOAD_MAX_LEN + 2;
 
 #[embassy_executor::task]
 pub async fn dev_task(mut dev: MyUsbDevice<'static>) {
@@ -24,6 +32,8 @@ pub async fn dev_task(mut dev: MyUsbDevice<'static>) {
 #[embassy_executor::task]
 pub async fn usb_io_task(midi: &'static Mutex<Cs, MyMidiClass<'static>>) {
     info!("Starting unified USB IO task");
+    // SysEx bytes collected so far, a message may span several USB packets
+    let mut rx: Vec<u8, SYSEX_RX_LEN> = Vec::new();
     loop {
         let read_fut = async {
             let mut buf = [0u8; 64];
@@ -76,8 +86,30 @@ pub async fn usb_io_task(midi: &'static Mutex<Cs, MyMidiClass<'static>>) {
                     }
                 };
 
+                if sysex_len == 0 {
+                    continue;
+                }
+                // Collect until F7, a new F0 drops whatever was left unfinished
+                if sysex_buf[0] == sysex::SYSEX_START || rx.last() == Some(&sysex::SYSEX_END) {
+                    rx.clear();
+                }
+                if rx.extend_from_slice(&sysex_buf[..sysex_len]).is_err() {
+                    error!("SysEx message too long for buffer");
+                    rx.clear();
+                    AT_CH
+                        .send(Msg::Err(
+                            0,
+                            FirmwareError::Proto(hexa_tune_proto::ProtoError::Overflow),
+                        ))
+                        .await;
+                    continue;
+                }
+                if rx.last() != Some(&sysex::SYSEX_END) {
+                    continue;
+                }
+
                 // Unframe SysEx → payload
-                let payload = match sysex::unframe(&sysex_buf[..sysex_len]) {
+                let payload = match sysex::unframe(&rx) {
                     Ok(p) => p,
                     Err(e) => {
                         error!("SysEx unframe error");
@@ -88,6 +120,27 @@ pub async fn usb_io_task(midi: &'static Mutex<Cs, MyMidiClass<'static>>) {
                     }
                 };
 
+                // Binary step chunks are verified here and handed to the DDS task
+                if payload.first() == Some(&BULK_MARKER) {
+                    match decode_bulk(payload) {
+                        Ok(chunk) => {
+                            let id = chunk.id;
+                            match queue_bulk(chunk) {
+                                Ok(()) => AT_CH.send(Msg::BulkChunk(id)).await,
+                                Err(e) => {
+                                    error!("Bulk chunk {} not queued", id);
+                                    AT_CH.send(reply(id, encode_nak(id, &e))).await;
+                                }
+                            }
+                        }
+                        Err((id, e)) => {
+                            error!("Bulk chunk rejected");
+                            AT_CH.send(reply(id, encode_nak(id, &e))).await;
+                        }
+                    }
+                    continue;
+                }
+
                 // Convert to UTF-8 string and send to AT channel
                 match core::str::from_utf8(payload) {
                     Ok(input) => match MsgString::try_from(input) {
@@ -120,15 +173,18 @@ pub async fn usb_io_task(midi: &'static Mutex<Cs, MyMidiClass<'static>>) {
 
             Either::Second(msg) => match msg {
                 Msg::UsbTxLine(line) => {
+                    // Without a host the write would block and back up every channel,
+                    // which would stall an operation autostarted on a power bank
+                    if !is_usb_configured() {
+                        info!("USB not configured, dropping TX line");
+                        continue;
+                    }
                     let line_bytes = line.as_bytes();
-                    let mut sysex_buf = [0u8; 128];
+                    let mut sysex_buf = [0u8; MSG_LEN + 2];
                     match sysex::frame(line_bytes, &mut sysex_buf) {
                         Ok(sysex_len) => {
-                            let mut packets = [[0u8; 4]; 32];
-                            match usb_midi::packetize(
-                                &sysex_buf[..sysex_len],
-                                &mut packets,
-                            ) {
+                            let mut packets = [[0u8; 4]; (MSG_LEN + 2).div_ceil(3)];
+                            match usb_midi::packetize(&sysex_buf[..sysex_len], &mut packets) {
                                 Ok(np) => {
                                     info!("Sending {} MIDI packets", np);
                                     let mut m = midi.lock().await;